@@ -0,0 +1,63 @@
+//! Heap footprint of a bucket's id keys: `Arc<str>` against `ClientId`.
+//! Every `Arc<str>` is its own allocation (two counters plus the bytes).
+//! With `inline_ids`, a `ClientId` of up to `INLINE_CAPACITY` bytes, UUIDs
+//! included, lives in the map's table with nothing behind it, but the key
+//! grows from 16 to 40 bytes: ids much shorter than that, and ids too long
+//! to inline, come out worse. Without the feature the two should match.
+//!
+//! Run with `cargo bench --bench client_ids --features inline_ids`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use unrust::actor::ClientId;
+
+const CLIENTS: usize = 1_000_000;
+
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Bytes still allocated after building the map, with the map alive.
+fn footprint<K: std::hash::Hash + Eq>(ids: &[String], key: impl Fn(&str) -> K) -> usize {
+    let before = LIVE.load(Ordering::Relaxed);
+    let map: HashMap<K, ()> = ids.iter().map(|id| (key(id), ())).collect();
+    let used = LIVE.load(Ordering::Relaxed) - before;
+    drop(map);
+    used
+}
+
+fn main() {
+    for (label, make) in [
+        ("short", (|i| format!("client{i}")) as fn(usize) -> String),
+        ("uuid", |i| format!("6f1c2a9e-3b7d-4c8e-9a21-{i:012x}")),
+        ("long", |i| format!("session-6f1c2a9e-3b7d-4c8e-9a21-{i:012x}")),
+    ] {
+        let ids: Vec<String> = (0..CLIENTS).map(make).collect();
+        let arc = footprint(&ids, |id| Arc::<str>::from(id));
+        let compact = footprint(&ids, |id| ClientId::from(id));
+        println!(
+            "{label:>5} ids: Arc<str> {:>6} MiB  ClientId {:>6} MiB  ({:.0}%)",
+            arc >> 20,
+            compact >> 20,
+            100.0 * compact as f64 / arc as f64,
+        );
+    }
+}
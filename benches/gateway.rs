@@ -0,0 +1,79 @@
+//! Mixed `get_is_active`/`set_is_active_sync` throughput across bucket
+//! counts and numbers of concurrent tasks, one in `WRITE_EVERY` calls a
+//! write. Each bucket owns its clients, so this measures how well that
+//! per-bucket ownership scales; run it before and after a change to the
+//! event loop or routing to compare.
+//!
+//! Run with `cargo bench --bench gateway`. Without `--bench`, as under
+//! `cargo test`, every configuration runs once with a handful of calls to
+//! check the harness still works; those numbers mean nothing.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use unrust::actor::GatewayService;
+
+const BUCKETS: [usize; 4] = [1, 2, 4, 8];
+const TASKS: [usize; 3] = [1, 16, 64];
+const WRITE_EVERY: usize = 10;
+
+struct Config {
+    clients: usize,
+    calls_per_task: usize,
+    /// Timed runs per configuration; the best is reported.
+    runs: usize,
+}
+
+const BENCH: Config = Config { clients: 10_000, calls_per_task: 2_000, runs: 3 };
+const SMOKE: Config = Config { clients: 100, calls_per_task: 10, runs: 1 };
+
+async fn calls_per_second(buckets: usize, tasks: usize, config: &Config) -> f64 {
+    let service = GatewayService::new(buckets);
+    let ids: Arc<Vec<Arc<str>>> = Arc::new((0..config.clients).map(|i| Arc::from(format!("client{i}"))).collect());
+    service.add_clients(ids.iter().cloned()).await.unwrap();
+
+    let service = Arc::new(service);
+    let start = Instant::now();
+    let workers: Vec<_> = (0..tasks)
+        .map(|task| {
+            let (service, ids) = (service.clone(), ids.clone());
+            let calls = config.calls_per_task;
+            tokio::spawn(async move {
+                for i in 0..calls {
+                    let id = ids[(task * calls + i) % ids.len()].clone();
+                    if i % WRITE_EVERY == 0 {
+                        service.set_is_active_sync(id, i % 2 == 0).await.unwrap();
+                    } else {
+                        service.get_is_active(id).await.unwrap();
+                    }
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.await.unwrap();
+    }
+    let rate = (tasks * config.calls_per_task) as f64 / start.elapsed().as_secs_f64();
+
+    Arc::into_inner(service).expect("workers have finished").shutdown().await;
+    rate
+}
+
+fn main() {
+    let bench = std::env::args().any(|arg| arg == "--bench");
+    let config = if bench { &BENCH } else { &SMOKE };
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    for buckets in BUCKETS {
+        for tasks in TASKS {
+            let best = (0..config.runs)
+                .map(|_| runtime.block_on(calls_per_second(buckets, tasks, config)))
+                .fold(0.0, f64::max);
+            if bench {
+                println!("{buckets} bucket(s), {tasks:>2} task(s): {best:.0} calls/s");
+            }
+        }
+    }
+    if !bench {
+        println!("gateway bench: smoke run ok");
+    }
+}
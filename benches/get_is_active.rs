@@ -0,0 +1,63 @@
+//! Concurrent `get_is_active` throughput. Each bucket owns its state, so
+//! reads to different buckets never wait on each other and there is no shared
+//! lock to serialize behind. On a multi-core machine throughput grows with the
+//! bucket count; on one core the numbers stay flat.
+//!
+//! Each configuration is also run against `get_is_active_snapshot`, which
+//! reads the buckets' published snapshots instead of messaging them.
+//!
+//! Run with `cargo bench --bench get_is_active`.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use unrust::actor::GatewayService;
+
+const CLIENTS: usize = 1_000;
+const READERS: usize = 64;
+const READS_PER_READER: usize = 2_000;
+
+async fn reads_per_second(buckets: usize, snapshot: bool) -> f64 {
+    let builder = GatewayService::builder().buckets(buckets);
+    let service = if snapshot { builder.read_snapshots().build() } else { builder.build() };
+    let ids: Arc<Vec<Arc<str>>> = Arc::new((0..CLIENTS).map(|i| Arc::from(format!("client{i}"))).collect());
+    for id in ids.iter() {
+        service.add_client_sync(id.clone()).await.unwrap();
+    }
+    while snapshot && service.get_is_active_snapshot(&ids[ids.len() - 1]).is_none() {
+        tokio::task::yield_now().await;
+    }
+
+    let service = Arc::new(service);
+    let start = Instant::now();
+    let readers: Vec<_> = (0..READERS)
+        .map(|reader| {
+            let (service, ids) = (service.clone(), ids.clone());
+            tokio::spawn(async move {
+                for i in 0..READS_PER_READER {
+                    let id = ids[(reader * READS_PER_READER + i) % ids.len()].clone();
+                    if snapshot {
+                        assert!(service.get_is_active_snapshot(&id).is_some());
+                        // let the other readers run, as an awaited read would
+                        tokio::task::yield_now().await;
+                    } else {
+                        service.get_is_active(id).await.unwrap();
+                    }
+                }
+            })
+        })
+        .collect();
+    for reader in readers {
+        reader.await.unwrap();
+    }
+    (READERS * READS_PER_READER) as f64 / start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    for buckets in [1, 2, 4, 8] {
+        let rate = runtime.block_on(reads_per_second(buckets, false));
+        let snapshot_rate = runtime.block_on(reads_per_second(buckets, true));
+        println!("{buckets} bucket(s): {rate:.0} reads/s, {snapshot_rate:.0} from snapshots");
+    }
+}
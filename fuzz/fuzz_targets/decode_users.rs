@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use unrust::serde_util::{decode, Users};
+
+// arbitrary bytes must decode or return `Err`, never panic or abort
+fuzz_target!(|data: &[u8]| {
+    let _ = decode::<Users>(data);
+});
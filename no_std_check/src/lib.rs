@@ -0,0 +1,84 @@
+//! Derives `Validate` in a `#![no_std]` crate, against `unrust_core` with
+//! only its `alloc` feature, so generated code that reaches for `std` fails
+//! to build. Build it on its own to keep `std` out of `unrust_core` too:
+//!
+//! ```text
+//! cargo build -p no_std_check
+//! ```
+
+#![no_std]
+
+extern crate alloc;
+#[cfg(test)]
+extern crate std;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use validate_macro::Validate;
+
+#[derive(Validate)]
+#[validate(crate = "unrust_core")]
+#[validate(fields_match(a = "pin", b = "confirm_pin"))]
+pub struct Device {
+    #[validate(non_empty, max_length = 16, starts_with = "dev-")]
+    pub name: String,
+    #[validate(range(min = 1, max = 8))]
+    pub channels: u8,
+    #[validate(email)]
+    pub owner: Option<String>,
+    #[validate(max_items = 4, unique(case_insensitive), each(min_length = 2))]
+    pub tags: Vec<String>,
+    #[validate(nested)]
+    pub sensors: Vec<Sensor>,
+    pub pin: u16,
+    pub confirm_pin: u16,
+}
+
+#[derive(Validate)]
+#[validate(crate = "unrust_core")]
+pub enum Sensor {
+    Thermometer {
+        #[validate(range(min = -40, max = 125))]
+        max_celsius: i16,
+    },
+    Switch(#[validate(one_of = ["on", "off"])] &'static str),
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    fn device() -> Device {
+        Device {
+            name: "dev-kitchen".to_string(),
+            channels: 2,
+            owner: Some("ann@example.com".to_string()),
+            tags: vec!["home".to_string(), "Lab".to_string()],
+            sensors: vec![Sensor::Thermometer { max_celsius: 80 }, Sensor::Switch("on")],
+            pin: 1234,
+            confirm_pin: 1234,
+        }
+    }
+
+    #[test]
+    fn test_validate_without_std() {
+        assert!(device().validate().is_ok());
+
+        let mut invalid = device();
+        invalid.name = "kitchen".to_string();
+        invalid.channels = 9;
+        invalid.tags.push("LAB".to_string());
+        invalid.sensors.push(Sensor::Switch("dim"));
+        invalid.confirm_pin = 4321;
+        let errors = invalid.validate().unwrap_err();
+        let codes: Vec<&str> = errors.iter().map(|error| error.code).collect();
+        assert_eq!(codes, ["starts_with", "range", "unique", "one_of", "fields_match"]);
+
+        // a BTreeMap, so sorted by field name
+        let fields: Vec<String> = invalid.validate_map().unwrap_err().into_keys().collect();
+        assert_eq!(fields, ["channels", "confirm_pin", "name", "sensors[2].0", "tags"]);
+    }
+}
@@ -0,0 +1,83 @@
+use std::io;
+use std::sync::Arc;
+
+use tokio::runtime::Runtime;
+
+use super::{GatewayError, GatewayService};
+
+/// A `GatewayService` for synchronous code. It owns the Tokio runtime its
+/// buckets run on, and every method blocks the calling thread until the
+/// bucket has answered.
+///
+/// Must not be used from within an async context: `Runtime::block_on`
+/// panics when called on a runtime thread, and blocking an executor thread
+/// would stall its other tasks anyway. Async code should use
+/// `GatewayService` directly.
+pub struct BlockingGateway {
+    runtime: Runtime,
+    service: GatewayService,
+}
+
+impl BlockingGateway {
+    /// Starts a multi-threaded runtime with `num_buckets` buckets on it.
+    pub fn new(num_buckets: usize) -> io::Result<Self> {
+        let runtime = Runtime::new()?;
+        let service = runtime.block_on(async { GatewayService::new(num_buckets) });
+        Ok(Self { runtime, service })
+    }
+
+    /// Like `GatewayService::add_client_sync`.
+    pub fn add_client(&self, client_id: impl Into<Arc<str>>) -> Result<bool, GatewayError> {
+        self.runtime.block_on(self.service.add_client_sync(client_id))
+    }
+
+    /// Like `GatewayService::set_is_active_sync`.
+    pub fn set_is_active(&self, client_id: impl Into<Arc<str>>, is_active: bool) -> Result<(), GatewayError> {
+        self.runtime.block_on(self.service.set_is_active_sync(client_id, is_active))
+    }
+
+    pub fn get_is_active(&self, client_id: impl Into<Arc<str>>) -> Result<bool, GatewayError> {
+        self.runtime.block_on(self.service.get_is_active(client_id))
+    }
+
+    /// Stops every bucket, then the runtime.
+    pub fn shutdown(self) {
+        self.runtime.block_on(self.service.shutdown());
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_blocking_round_trip() {
+        let gateway = BlockingGateway::new(4).unwrap();
+        assert_eq!(gateway.add_client("client123"), Ok(true));
+        assert_eq!(gateway.add_client("client123"), Ok(false));
+        assert_eq!(gateway.get_is_active("client123"), Ok(false));
+        gateway.set_is_active("client123", true).unwrap();
+        assert_eq!(gateway.get_is_active("client123"), Ok(true));
+        gateway.shutdown();
+    }
+
+    #[test]
+    fn test_blocking_from_several_threads() {
+        let gateway = BlockingGateway::new(2).unwrap();
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let gateway = &gateway;
+                scope.spawn(move || {
+                    for i in 0..25 {
+                        let id = format!("client{thread}-{i}");
+                        gateway.add_client(id.as_str()).unwrap();
+                        gateway.set_is_active(id.as_str(), true).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(gateway.get_is_active("client3-24"), Ok(true));
+        assert_eq!(gateway.runtime.block_on(gateway.service.total_client_count()), Ok(100));
+    }
+}
@@ -0,0 +1,191 @@
+use std::borrow::Borrow;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// Longest id stored inline with the `inline_ids` feature; anything longer
+/// keeps its `Arc<str>`. Enough for a hyphenated UUID.
+#[cfg(feature = "inline_ids")]
+pub const INLINE_CAPACITY: usize = 38;
+
+/// A client id as a `Gateway` stores it. With the `inline_ids` feature, ids
+/// of up to `INLINE_CAPACITY` bytes live inside the 40-byte value with no
+/// heap allocation; otherwise, and for longer ids, it is the 16-byte
+/// `Arc<str>` the command carried. Either way it hashes, compares and
+/// borrows as the `str` it holds, so maps keyed on it look up by `&str`.
+#[derive(Clone)]
+pub struct ClientId(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    #[cfg(feature = "inline_ids")]
+    Inline { len: u8, bytes: [u8; INLINE_CAPACITY] },
+    Heap(Arc<str>),
+}
+
+impl ClientId {
+    pub fn as_str(&self) -> &str {
+        match &self.0 {
+            // SAFETY: `inline` is the only constructor, and it copies all of
+            // a `&str` into `bytes[..len]`, so they are valid UTF-8.
+            #[cfg(feature = "inline_ids")]
+            Repr::Inline { len, bytes } => unsafe { std::str::from_utf8_unchecked(&bytes[..*len as usize]) },
+            Repr::Heap(id) => id,
+        }
+    }
+
+    /// Always false without the `inline_ids` feature.
+    pub fn is_inline(&self) -> bool {
+        match self.0 {
+            #[cfg(feature = "inline_ids")]
+            Repr::Inline { .. } => true,
+            Repr::Heap(_) => false,
+        }
+    }
+
+    /// The id as an `Arc<str>`; allocates only for inline ids.
+    pub fn to_arc(&self) -> Arc<str> {
+        match &self.0 {
+            #[cfg(feature = "inline_ids")]
+            Repr::Inline { .. } => Arc::from(self.as_str()),
+            Repr::Heap(id) => id.clone(),
+        }
+    }
+
+    #[cfg(not(feature = "inline_ids"))]
+    fn inline(_id: &str) -> Option<Self> {
+        None
+    }
+
+    #[cfg(feature = "inline_ids")]
+    fn inline(id: &str) -> Option<Self> {
+        if id.len() > INLINE_CAPACITY {
+            return None;
+        }
+        let mut bytes = [0; INLINE_CAPACITY];
+        bytes[..id.len()].copy_from_slice(id.as_bytes());
+        Some(Self(Repr::Inline { len: id.len() as u8, bytes }))
+    }
+}
+
+impl From<Arc<str>> for ClientId {
+    fn from(id: Arc<str>) -> Self {
+        Self::inline(&id).unwrap_or(Self(Repr::Heap(id)))
+    }
+}
+
+impl From<&str> for ClientId {
+    fn from(id: &str) -> Self {
+        Self::inline(id).unwrap_or_else(|| Self(Repr::Heap(Arc::from(id))))
+    }
+}
+
+impl Deref for ClientId {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Borrow<str> for ClientId {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq for ClientId {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for ClientId {}
+
+/// Must match `str`'s `Hash` for the `Borrow<str>` lookups to work.
+impl Hash for ClientId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    #[cfg(not(feature = "inline_ids"))]
+    fn test_is_an_arc_without_inline_ids() {
+        assert_eq!(std::mem::size_of::<ClientId>(), 16);
+        let arc: Arc<str> = Arc::from("client123");
+        let id = ClientId::from(arc.clone());
+        assert!(!id.is_inline());
+        assert!(Arc::ptr_eq(&id.to_arc(), &arc));
+    }
+
+    #[test]
+    #[cfg(feature = "inline_ids")]
+    fn test_fits_in_40_bytes() {
+        assert_eq!(std::mem::size_of::<ClientId>(), 40);
+    }
+
+    #[test]
+    #[cfg(feature = "inline_ids")]
+    fn test_short_ids_are_inline() {
+        let id = ClientId::from(Arc::<str>::from("client123"));
+        assert!(id.is_inline());
+        assert_eq!(id.as_str(), "client123");
+        assert!(ClientId::from("").is_inline());
+        assert!(ClientId::from("a".repeat(INLINE_CAPACITY).as_str()).is_inline());
+    }
+
+    #[test]
+    #[cfg(feature = "inline_ids")]
+    fn test_uuids_are_inline() {
+        let id = ClientId::from("6f1c2a9e-3b7d-4c8e-9a21-5d0f7b3e8c44");
+        assert!(id.is_inline());
+        assert_eq!(id.as_str(), "6f1c2a9e-3b7d-4c8e-9a21-5d0f7b3e8c44");
+        assert!(!ClientId::from("a".repeat(INLINE_CAPACITY + 1).as_str()).is_inline());
+    }
+
+    #[test]
+    fn test_long_ids_share_the_arc() {
+        let arc: Arc<str> = Arc::from("session-6f1c2a9e-3b7d-4c8e-9a21-5d0f7b3e8c44");
+        let id = ClientId::from(arc.clone());
+        assert!(!id.is_inline());
+        assert!(Arc::ptr_eq(&id.to_arc(), &arc));
+    }
+
+    #[test]
+    fn test_map_lookup_by_str() {
+        let mut map = HashMap::new();
+        map.insert(ClientId::from("short"), 1);
+        map.insert(ClientId::from("a-much-longer-session-token-id-past-the-inline-limit"), 2);
+        assert_eq!(map.get("short"), Some(&1));
+        assert_eq!(map.get("a-much-longer-session-token-id-past-the-inline-limit"), Some(&2));
+        assert_eq!(map.get("missing"), None);
+    }
+
+    #[test]
+    fn test_non_ascii() {
+        let id = ClientId::from("клиент");
+        assert_eq!(id.is_inline(), cfg!(feature = "inline_ids"));
+        assert_eq!(id.to_string(), "клиент");
+        assert_eq!(format!("{id:?}"), "\"клиент\"");
+    }
+}
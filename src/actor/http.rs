@@ -0,0 +1,250 @@
+//! A small HTTP/1.1 admin interface over a `GatewayService`, for ops tooling:
+//!
+//! - `POST /clients/{id}` adds the client: `201` if new, `200` if it existed.
+//! - `PUT /clients/{id}/active` with a `{"is_active": bool}` body sets it.
+//! - `GET /clients/{id}/active` reads it.
+//!
+//! Every response is JSON and closes the connection. Only what these routes
+//! need is implemented: no keep-alive, chunked bodies or percent-decoding.
+
+use std::hash::BuildHasher;
+use std::io;
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Take};
+use tokio::net::{TcpListener, TcpStream};
+
+use super::{GatewayError, GatewayService};
+
+/// Request heads or bodies longer than this are rejected with `413`.
+pub const MAX_REQUEST_SIZE: usize = 16 * 1024;
+
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+/// Accepts connections on `listener` until accepting fails, handling each on
+/// its own task.
+pub async fn serve<S, V>(listener: TcpListener, service: Arc<GatewayService<S, V>>) -> io::Result<()>
+where
+    S: BuildHasher + Send + Sync + 'static,
+    V: Clone + Default + Send + Sync + 'static,
+{
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let service = service.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, &service).await {
+                eprintln!("admin connection failed: {e}");
+            }
+        });
+    }
+}
+
+async fn handle<S, V>(mut stream: TcpStream, service: &GatewayService<S, V>) -> io::Result<()>
+where
+    S: BuildHasher,
+    V: Clone + Default + Send + Sync + 'static,
+{
+    let (reader, mut writer) = stream.split();
+    let (status, body) = match read_request(&mut BufReader::new(reader)).await? {
+        Ok(request) => route(service, &request).await,
+        Err(response) => response,
+    };
+    let body = body.to_string();
+    let head = format!(
+        "HTTP/1.1 {status} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        reason(status),
+        body.len(),
+    );
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(body.as_bytes()).await?;
+    writer.shutdown().await
+}
+
+/// Reads one request. A malformed one comes back as the error response to
+/// send instead.
+async fn read_request<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> io::Result<Result<Request, (u16, Value)>> {
+    // Bounding the reader itself, rather than checking after each line, stops
+    // a peer that never sends a newline from growing `line` without limit.
+    let mut head = (&mut *reader).take(MAX_REQUEST_SIZE as u64);
+    let mut line = String::new();
+    head.read_line(&mut line).await?;
+    if head_too_large(&head, &line) {
+        return Ok(Err(error(413, "request head too large")));
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path), Some(_version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Ok(Err(error(400, "malformed request line")));
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        head.read_line(&mut line).await?;
+        if head_too_large(&head, &line) {
+            return Ok(Err(error(413, "request head too large")));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                let Ok(len) = value.trim().parse::<usize>() else {
+                    return Ok(Err(error(400, "invalid Content-Length")));
+                };
+                content_length = len;
+            }
+        }
+    }
+    if content_length > MAX_REQUEST_SIZE {
+        return Ok(Err(error(413, "request body too large")));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+    Ok(Ok(Request { method, path, body }))
+}
+
+/// Whether reading `line` stopped at the head's size limit rather than at a
+/// newline.
+fn head_too_large<R: AsyncReadExt>(head: &Take<R>, line: &str) -> bool {
+    head.limit() == 0 && !line.ends_with('\n')
+}
+
+async fn route<S, V>(service: &GatewayService<S, V>, request: &Request) -> (u16, Value)
+where
+    S: BuildHasher,
+    V: Clone + Default + Send + Sync + 'static,
+{
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("POST", ["clients", id]) => {
+            let client_id: Arc<str> = Arc::from(*id);
+            match service.add_client_sync(client_id.clone()).await {
+                Ok(added) => (if added { 201 } else { 200 }, json!({ "client_id": &*client_id, "added": added })),
+                Err(e) => gateway_error(e),
+            }
+        }
+        ("PUT", ["clients", id, "active"]) => {
+            let Some(is_active) = serde_json::from_slice::<Value>(&request.body)
+                .ok()
+                .and_then(|body| body.get("is_active")?.as_bool())
+            else {
+                return error(400, "expected a body like {\"is_active\": true}");
+            };
+            match service.set_is_active_sync(*id, is_active).await {
+                Ok(()) => (200, json!({ "client_id": id, "is_active": is_active })),
+                Err(e) => gateway_error(e),
+            }
+        }
+        ("GET", ["clients", id, "active"]) => match service.get_is_active(*id).await {
+            Ok(is_active) => (200, json!({ "client_id": id, "is_active": is_active })),
+            Err(e) => gateway_error(e),
+        },
+        (_, ["clients", _]) | (_, ["clients", _, "active"]) => error(405, "method not allowed"),
+        _ => error(404, "not found"),
+    }
+}
+
+fn gateway_error(e: GatewayError) -> (u16, Value) {
+    let status = match e {
+        GatewayError::RateLimited => 429,
+        GatewayError::Timeout => 504,
+        _ => 503,
+    };
+    error(status, &e.to_string())
+}
+
+fn error(status: u16, message: &str) -> (u16, Value) {
+    (status, json!({ "error": message }))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        429 => "Too Many Requests",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    /// Serves a 2-bucket gateway on an ephemeral port and returns its address.
+    async fn start() -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::new(GatewayService::new(2))));
+        addr
+    }
+
+    /// Sends a raw request and returns the status code and JSON body.
+    async fn request(addr: std::net::SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{body}", body.len());
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_routes() {
+        let addr = start().await;
+        assert_eq!(request(addr, "POST", "/clients/client123", "").await, (201, json!({ "client_id": "client123", "added": true })));
+        assert_eq!(request(addr, "POST", "/clients/client123", "").await, (200, json!({ "client_id": "client123", "added": false })));
+        assert_eq!(
+            request(addr, "GET", "/clients/client123/active", "").await,
+            (200, json!({ "client_id": "client123", "is_active": false }))
+        );
+        assert_eq!(
+            request(addr, "PUT", "/clients/client123/active", r#"{"is_active": true}"#).await,
+            (200, json!({ "client_id": "client123", "is_active": true }))
+        );
+        assert_eq!(
+            request(addr, "GET", "/clients/client123/active", "").await,
+            (200, json!({ "client_id": "client123", "is_active": true }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bad_requests() {
+        let addr = start().await;
+        assert_eq!(request(addr, "PUT", "/clients/client123/active", "yes").await.0, 400);
+        assert_eq!(request(addr, "PUT", "/clients/client123/active", r#"{"is_active": 1}"#).await.0, 400);
+        assert_eq!(request(addr, "DELETE", "/clients/client123", "").await.0, 405);
+        assert_eq!(request(addr, "GET", "/buckets", "").await, (404, json!({ "error": "not found" })));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_head_without_newline() {
+        let addr = start().await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut head = b"GET /clients/client123/active HTTP/1.1\r\nX-Padding: ".to_vec();
+        head.resize(MAX_REQUEST_SIZE, b'a');
+        // The write side stays open, so only the size limit can end the read.
+        stream.write_all(&head).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 413 "), "{response}");
+    }
+}
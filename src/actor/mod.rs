@@ -1,169 +1,3385 @@
+use bincode::{Decode, Encode};
+use futures::{stream, Stream, StreamExt};
 use std::{
-    collections::HashMap,
-    hash::{Hash, Hasher},
-    sync::Arc, thread, time::Duration,
+    collections::HashMap, fmt, fs::File, io::{self, BufReader, BufWriter}, path::Path,
+    hash::{BuildHasher, BuildHasherDefault}, marker::PhantomData,
+    sync::Arc, time::{Duration, Instant},
 };
-use tokio::sync::mpsc::{self, Sender, Receiver, channel};
-use tokio::sync::Mutex;
+use tokio::sync::mpsc::{self, error::TrySendError, Sender, Receiver};
+use tokio::sync::{broadcast, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::Interval;
 use std::collections::hash_map::DefaultHasher;
+use unrust_core::jump_hash;
 
-enum Commands {
-    SetIsActive {
+use read_snapshot::ReadSnapshot;
+
+mod blocking;
+mod client_id;
+#[cfg(feature = "http")]
+pub mod http;
+mod parse;
+mod read_snapshot;
+pub use blocking::BlockingGateway;
+pub use client_id::ClientId;
+pub use parse::{parse_command, ParseError, ParsedCommand};
+
+/// `V` is the value type each client carries besides `is_active`; see
+/// `GatewayServiceBuilder::value`.
+enum Commands<V = ()> {
+    SetState {
         client_id: Arc<str>,
-        is_active: bool,
+        state: ClientState,
+        ack: Option<oneshot::Sender<Result<(), GatewayError>>>,
     },
     GetIsActive {
         client_id: Arc<str>,
-        sender: Sender<bool>
+        sender: oneshot::Sender<bool>
+    },
+    /// Replies `None` if the client does not exist.
+    GetState {
+        client_id: Arc<str>,
+        sender: oneshot::Sender<Option<ClientState>>
+    },
+    /// Sets `is_active` to `new` only if it is currently `expected`, and
+    /// replies with whether it did.
+    CompareAndSet {
+        client_id: Arc<str>,
+        expected: bool,
+        new: bool,
+        sender: oneshot::Sender<Result<bool, GatewayError>>
+    },
+    /// Adds the client if it is missing and replies with its `is_active`.
+    GetOrCreate {
+        client_id: Arc<str>,
+        sender: oneshot::Sender<Result<bool, GatewayError>>
     },
+    /// Acks `true` if the client is new, `false` if it already existed.
     AddClient {
         client_id: Arc<str>,
+        ack: Option<oneshot::Sender<Result<bool, GatewayError>>>,
+    },
+    /// Adds several clients of the same bucket in one message.
+    AddClients {
+        client_ids: Vec<Arc<str>>,
+        sender: oneshot::Sender<Result<(), GatewayError>>
+    },
+    RemoveClient {
+        client_id: Arc<str>,
+        sender: oneshot::Sender<bool>
+    },
+    SetAllActive {
+        is_active: bool,
+        sender: oneshot::Sender<Result<(), GatewayError>>
+    },
+    Count {
+        sender: oneshot::Sender<usize>
+    },
+    Sweep {
+        ttl: Duration,
+        sender: oneshot::Sender<Vec<String>>
+    },
+    ListActive {
+        sender: oneshot::Sender<Vec<String>>
+    },
+    /// Replies `false` if the client does not exist.
+    SetMeta {
+        client_id: Arc<str>,
+        key: String,
+        value: String,
+        sender: oneshot::Sender<Result<bool, GatewayError>>
+    },
+    GetMeta {
+        client_id: Arc<str>,
+        key: String,
+        sender: oneshot::Sender<Option<String>>
+    },
+    /// Replies `false` if the client does not exist.
+    SetValue {
+        client_id: Arc<str>,
+        value: V,
+        sender: oneshot::Sender<Result<bool, GatewayError>>
+    },
+    GetValue {
+        client_id: Arc<str>,
+        sender: oneshot::Sender<Option<V>>
+    },
+    /// Marks the client active and refreshes `last_seen` in one message.
+    Heartbeat {
+        client_id: Arc<str>,
+    },
+    /// Copies out every client in the bucket for persistence.
+    Export {
+        sender: oneshot::Sender<Vec<ClientRecord<V>>>
+    },
+    /// Restores previously exported clients. With `overwrite` false, a
+    /// client the bucket already has is kept as it is.
+    Import {
+        records: Vec<ClientRecord<V>>,
+        overwrite: bool,
+        sender: oneshot::Sender<Result<(), GatewayError>>
+    },
+    /// Removes the given clients and replies with what they held.
+    Extract {
+        client_ids: Vec<Arc<str>>,
+        sender: oneshot::Sender<Vec<ClientRecord<V>>>
+    },
+    /// Streams every `(client_id, is_active)` pair in the bucket to `sender`,
+    /// `chunk_size` at a time, waiting for room between chunks.
+    Scan {
+        chunk_size: usize,
+        sender: Sender<Vec<(String, bool)>>
+    },
+    /// Copies out every `(client_id, is_active)` pair in the bucket.
+    Snapshot {
+        sender: oneshot::Sender<Vec<(String, bool)>>
+    },
+    /// Replies with the bucket's counters as of before this command.
+    Stats {
+        sender: oneshot::Sender<BucketStats>
+    },
+    /// From here on the bucket rejects commands that add clients or make
+    /// them active, such as `AddClient` and `SetState`, with
+    /// `GatewayError::Draining`; a `Heartbeat` is dropped. Replies once
+    /// everything queued ahead of it has been processed.
+    Drain {
+        sender: oneshot::Sender<()>
+    },
+    /// Does nothing but reply, to show the event loop is still running.
+    /// Being queued like any other command, the reply also shows that
+    /// everything sent to the bucket before it has been processed.
+    Ping {
+        sender: oneshot::Sender<()>
+    },
+    /// Stops the event loop; commands queued behind it are dropped.
+    Shutdown,
+}
+
+impl<V> Commands<V> {
+    fn name(&self) -> &'static str {
+        match self {
+            Commands::SetState { .. } => "SetState",
+            Commands::GetIsActive { .. } => "GetIsActive",
+            Commands::GetState { .. } => "GetState",
+            Commands::CompareAndSet { .. } => "CompareAndSet",
+            Commands::GetOrCreate { .. } => "GetOrCreate",
+            Commands::AddClient { .. } => "AddClient",
+            Commands::AddClients { .. } => "AddClients",
+            Commands::RemoveClient { .. } => "RemoveClient",
+            Commands::SetAllActive { .. } => "SetAllActive",
+            Commands::Count { .. } => "Count",
+            Commands::Sweep { .. } => "Sweep",
+            Commands::ListActive { .. } => "ListActive",
+            Commands::SetMeta { .. } => "SetMeta",
+            Commands::GetMeta { .. } => "GetMeta",
+            Commands::SetValue { .. } => "SetValue",
+            Commands::GetValue { .. } => "GetValue",
+            Commands::Heartbeat { .. } => "Heartbeat",
+            Commands::Export { .. } => "Export",
+            Commands::Import { .. } => "Import",
+            Commands::Extract { .. } => "Extract",
+            Commands::Scan { .. } => "Scan",
+            Commands::Snapshot { .. } => "Snapshot",
+            Commands::Stats { .. } => "Stats",
+            Commands::Drain { .. } => "Drain",
+            Commands::Ping { .. } => "Ping",
+            Commands::Shutdown => "Shutdown",
+        }
+    }
+
+    /// Whether the command can add or remove clients or change `is_active`,
+    /// leaving the bucket's read snapshot stale.
+    fn changes_flags(&self) -> bool {
+        match self {
+            Commands::SetState { .. }
+            | Commands::CompareAndSet { .. }
+            | Commands::GetOrCreate { .. }
+            | Commands::AddClient { .. }
+            | Commands::AddClients { .. }
+            | Commands::RemoveClient { .. }
+            | Commands::SetAllActive { .. }
+            | Commands::Sweep { .. }
+            | Commands::Heartbeat { .. }
+            | Commands::Import { .. }
+            | Commands::Extract { .. } => true,
+            Commands::GetIsActive { .. }
+            | Commands::GetState { .. }
+            | Commands::Count { .. }
+            | Commands::ListActive { .. }
+            | Commands::SetMeta { .. }
+            | Commands::GetMeta { .. }
+            | Commands::SetValue { .. }
+            | Commands::GetValue { .. }
+            | Commands::Export { .. }
+            | Commands::Scan { .. }
+            | Commands::Snapshot { .. }
+            | Commands::Stats { .. }
+            | Commands::Drain { .. }
+            | Commands::Ping { .. }
+            | Commands::Shutdown => false,
+        }
+    }
+}
+
+/// A snapshot of one bucket's load.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BucketStats {
+    /// Commands the event loop has processed.
+    pub commands_received: u64,
+    /// `commands_received` broken down by command name, e.g. `"AddClient"`.
+    pub commands_by_type: HashMap<&'static str, u64>,
+    pub client_count: usize,
+    /// Free slots in the bucket's queue when the snapshot was taken.
+    pub queue_capacity: usize,
+    /// The queue's size; `max_capacity - queue_capacity` commands are waiting.
+    pub max_capacity: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum GatewayError {
+    BucketNotFound(usize),
+    SendFailed,
+    RecvFailed,
+    /// The bucket's queue is at capacity; returned by the `try_*` methods.
+    Full,
+    /// The client has used up its command budget; see `RateLimit`.
+    RateLimited,
+    /// The bucket did not answer in time.
+    Timeout,
+    /// The bucket is being drained and takes no new writes; see `drain`.
+    Draining,
+}
+
+impl fmt::Display for GatewayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GatewayError::BucketNotFound(bucket) => write!(f, "bucket {bucket} not found"),
+            GatewayError::SendFailed => f.write_str("failed to send command: bucket is closed"),
+            GatewayError::RecvFailed => f.write_str("bucket dropped the command without replying"),
+            GatewayError::Full => f.write_str("bucket queue is full"),
+            GatewayError::RateLimited => f.write_str("client is rate limited"),
+            GatewayError::Timeout => f.write_str("timed out waiting for bucket"),
+            GatewayError::Draining => f.write_str("bucket is draining"),
+        }
+    }
+}
+
+impl std::error::Error for GatewayError {}
+
+/// Where a client's connection stands. Only `Active` counts as active for
+/// `is_active` and everything built on it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClientState {
+    /// Known, but not yet active; where every new client starts.
+    #[default]
+    Connecting,
+    Active,
+    /// On its way out: still known, no longer active.
+    Draining,
+    Disconnected,
+}
+
+impl ClientState {
+    pub fn is_active(self) -> bool {
+        self == ClientState::Active
+    }
+}
+
+/// What the `is_active` flag maps to: `true` is `Active`, `false` is
+/// `Disconnected`.
+impl From<bool> for ClientState {
+    fn from(is_active: bool) -> Self {
+        if is_active {
+            ClientState::Active
+        } else {
+            ClientState::Disconnected
+        }
+    }
+}
+
+struct Client<V> {
+    state: ClientState,
+    /// Starts as `V::default()`; replaced by `SetValue`.
+    value: V,
+    /// Refreshed by every command that writes to this client.
+    last_seen: Instant,
+    /// Free-form attributes such as display name, protocol or region.
+    metadata: HashMap<String, String>,
+    /// Token bucket for `RateLimit`; starts full.
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl<V: Default> Client<V> {
+    /// Starts from a client that was just seen: `Connecting`, with
+    /// `V::default()`, no metadata and an empty token bucket.
+    fn builder() -> ClientBuilder<V> {
+        let now = Instant::now();
+        ClientBuilder {
+            client: Client { state: ClientState::default(), value: V::default(), last_seen: now, metadata: HashMap::new(), tokens: 0.0, last_refill: now },
+        }
+    }
+}
+
+impl<V> Client<V> {
+    fn is_active(&self) -> bool {
+        self.state.is_active()
+    }
+}
+
+struct ClientBuilder<V> {
+    client: Client<V>,
+}
+
+impl<V> ClientBuilder<V> {
+    fn state(mut self, state: ClientState) -> Self {
+        self.client.state = state;
+        self
+    }
+
+    fn value(mut self, value: V) -> Self {
+        self.client.value = value;
+        self
+    }
+
+    fn metadata(mut self, metadata: HashMap<String, String>) -> Self {
+        self.client.metadata = metadata;
+        self
+    }
+
+    /// Tokens the client's rate limit bucket starts with.
+    fn tokens(mut self, tokens: f64) -> Self {
+        self.client.tokens = tokens;
+        self
+    }
+
+    fn build(self) -> Client<V> {
+        self.client
     }
 }
 
-struct Client {
+/// The persistent part of a `Client`. `last_seen` is an `Instant`, which has
+/// no meaning across restarts, so restored clients count as just seen. Only
+/// whether the client is active is kept; others restore as `Disconnected`.
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+struct ClientRecord<V = ()> {
+    client_id: String,
     is_active: bool,
+    value: V,
+    metadata: HashMap<String, String>,
+}
+
+/// What `GatewayService::save` writes: every client, bincode-encoded with
+/// the standard config.
+#[derive(Encode, Decode, Debug, PartialEq)]
+struct GatewaySnapshot<V = ()> {
+    clients: Vec<ClientRecord<V>>,
+}
+
+impl<V> GatewaySnapshot<V> {
+    fn save(&self, path: &Path) -> io::Result<()>
+    where
+        V: Encode,
+    {
+        let mut writer = BufWriter::new(File::create(path)?);
+        crate::serde_util::encode_into(self, &mut writer).map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    /// Not capped at `DECODE_LIMIT`: bincode charges a `Vec` its whole
+    /// in-memory size against the limit, so any gateway past a few hundred
+    /// thousand clients could be saved but never loaded again. The file is
+    /// our own `save` output, not untrusted input.
+    fn load(path: &Path) -> io::Result<Self>
+    where
+        V: Decode<()>,
+    {
+        let mut reader = BufReader::new(File::open(path)?);
+        crate::serde_util::decode_from_with_config(&mut reader, bincode::config::standard())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+struct Gateway<V = ()> {
+    /// Ids keep the `Arc<str>` the command carried, or with `inline_ids`
+    /// short ones are copied inline, so lookups and inserts never allocate.
+    clients: HashMap<ClientId, Client<V>>,
+    rate_limit: Option<RateLimit>,
+    events: broadcast::Sender<StateChange>,
+    /// Where `publish_snapshot` puts the flags, if read snapshots are on.
+    read_snapshot: Option<Arc<ReadSnapshot>>,
+}
+
+/// Emitted to subscribers whenever a client's `is_active` flips.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StateChange {
+    pub client_id: Arc<str>,
+    pub is_active: bool,
+}
+
+/// Each bucket's queue size unless set with `GatewayServiceBuilder::channel_capacity`.
+pub const CHANNEL_CAPACITY: usize = 1024;
+/// With read snapshots on, a bucket that never finds its queue empty still
+/// publishes after this many flag-changing commands.
+const SNAPSHOT_MAX_BATCH: usize = 1024;
+/// How long `ping_all` waits for the buckets to answer.
+pub const PING_TIMEOUT: Duration = Duration::from_secs(1);
+const EVENT_CAPACITY: usize = 1024;
+
+/// Makes every bucket evict clients not seen for `ttl`, checking `every` tick.
+#[derive(Clone, Copy, Debug)]
+pub struct SweepConfig {
+    pub every: Duration,
+    pub ttl: Duration,
 }
 
-struct Gateway {
-    clients: HashMap<String, Client>
+/// Caps each client to `per_second` writes, allowing bursts of up to `burst`.
+/// Writes over budget are rejected with `GatewayError::RateLimited`, or
+/// dropped if the command has no reply channel.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
 }
 
-struct GatewayService {
-    clients: Vec<Sender<Commands>>
+/// `DefaultHasher` with its fixed keys: the same client id maps to the same
+/// bucket in every service, but only for a given Rust version. Pin a hasher of
+/// your own with `GatewayService::with_hasher` if mappings must survive
+/// toolchain upgrades or be shared across machines.
+pub type DefaultBuildHasher = BuildHasherDefault<DefaultHasher>;
+
+/// `V` is a value stored per client next to `is_active`, such as a session
+/// token or a counter; it defaults to `()`. Pick it with
+/// `GatewayServiceBuilder::value`.
+///
+/// Commands a task sends for a client are applied in the order it sent them,
+/// even fire-and-forget ones: a client always maps to one bucket, whose queue
+/// is first-in first-out and whose event loop handles one command at a time.
+/// Commands from different tasks interleave in whatever order they reach the
+/// queue.
+pub struct GatewayService<S = DefaultBuildHasher, V = ()> {
+    clients: Vec<Sender<Commands<V>>>,
+    handles: Vec<JoinHandle<()>>,
+    sweep: Option<SweepConfig>,
+    rate_limit: Option<RateLimit>,
+    request_timeout: Option<Duration>,
+    channel_capacity: usize,
+    /// One per bucket; a bucket gets keys in proportion to its weight.
+    weights: Vec<u32>,
+    /// Mixed into every id's hash before routing; 0 leaves it unchanged.
+    seed: u64,
+    read_snapshots: bool,
+    /// One per bucket when `read_snapshots` is on, otherwise empty.
+    snapshots: Vec<Arc<ReadSnapshot>>,
+    events: broadcast::Sender<StateChange>,
+    hasher: S,
 }
 
 impl GatewayService {
+    /// A service with `num_buckets` running buckets and default settings.
+    /// Must be called from within a Tokio runtime.
+    pub fn new(num_buckets: usize) -> Self {
+        Self::builder().buckets(num_buckets).build()
+    }
 
-    fn get_bucket(&self, client_id: &str) -> usize {
-        let mut hasher = DefaultHasher::new();
-        client_id.hash(&mut hasher);
-        let final_hash = hasher.finish();
+    /// Like `new`, but every bucket's queue holds `channel_capacity`
+    /// commands instead of `CHANNEL_CAPACITY`.
+    pub fn with_capacity(num_buckets: usize, channel_capacity: usize) -> Self {
+        Self::builder().buckets(num_buckets).channel_capacity(channel_capacity).build()
+    }
 
-        jump_hash(final_hash, self.clients.len() as i64) as usize
+    pub fn builder() -> GatewayServiceBuilder {
+        GatewayServiceBuilder {
+            buckets: 1,
+            sweep: None,
+            rate_limit: None,
+            request_timeout: None,
+            channel_capacity: CHANNEL_CAPACITY,
+            seed: 0,
+            read_snapshots: false,
+            hasher: DefaultBuildHasher::default(),
+            value: PhantomData,
+        }
     }
+}
 
-    async fn send_command(&self, client_id: Arc<str>, command: Commands) {
-        let bucket = self.get_bucket(&client_id);
-        if let Some(sender) = self.clients.get(bucket) {
-            if let Err(e) = sender.send(command).await {
-                eprintln!("SendError: {e}");
-            }
-        } else {
-            eprintln!("Client bucket not found for id: {client_id}");
+/// Configures a `GatewayService` before its buckets are spawned.
+pub struct GatewayServiceBuilder<S = DefaultBuildHasher, V = ()> {
+    buckets: usize,
+    sweep: Option<SweepConfig>,
+    rate_limit: Option<RateLimit>,
+    request_timeout: Option<Duration>,
+    channel_capacity: usize,
+    seed: u64,
+    read_snapshots: bool,
+    hasher: S,
+    value: PhantomData<fn() -> V>,
+}
+
+impl<S: BuildHasher, V: Clone + Default + Send + Sync + 'static> GatewayServiceBuilder<S, V> {
+    /// Number of buckets to start with; defaults to 1.
+    pub fn buckets(mut self, buckets: usize) -> Self {
+        self.buckets = buckets;
+        self
+    }
+
+    pub fn sweep(mut self, sweep: SweepConfig) -> Self {
+        self.sweep = Some(sweep);
+        self
+    }
+
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// How many commands each bucket queues before senders wait (or the
+    /// `try_*` methods fail with `Full`). Smaller means tighter backpressure,
+    /// larger absorbs bursts. Must be non-zero.
+    pub fn channel_capacity(mut self, channel_capacity: usize) -> Self {
+        assert!(channel_capacity > 0, "channel capacity must be non-zero");
+        self.channel_capacity = channel_capacity;
+        self
+    }
+
+    /// See `GatewayService::set_request_timeout`.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// See `GatewayService::set_seed`.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Has every bucket publish its `is_active` flags after each batch of
+    /// writes, for `GatewayService::get_is_active_snapshot` to read without
+    /// a round trip through the bucket. Each publish copies the bucket's
+    /// flags, so this suits read-heavy loads.
+    pub fn read_snapshots(mut self) -> Self {
+        self.read_snapshots = true;
+        self
+    }
+
+    pub fn hasher<H: BuildHasher>(self, hasher: H) -> GatewayServiceBuilder<H, V> {
+        GatewayServiceBuilder {
+            buckets: self.buckets,
+            sweep: self.sweep,
+            rate_limit: self.rate_limit,
+            request_timeout: self.request_timeout,
+            channel_capacity: self.channel_capacity,
+            seed: self.seed,
+            read_snapshots: self.read_snapshots,
+            hasher,
+            value: PhantomData,
         }
     }
 
-    async fn add_client(&self, client_id: Arc<str>) {
-        let client = self.get_bucket(&client_id);
-        println!("add_client_id {client_id}");
-        let x = self.clients[client].clone();
-        if let Err(e) = x.send(Commands::AddClient { client_id }).await {
-            println!("SendError: {e}");
+    /// The type of the value each client carries, read and written with
+    /// `get_value` and `set_value`. New clients start at `W::default()`.
+    pub fn value<W: Clone + Default + Send + Sync + 'static>(self) -> GatewayServiceBuilder<S, W> {
+        GatewayServiceBuilder {
+            buckets: self.buckets,
+            sweep: self.sweep,
+            rate_limit: self.rate_limit,
+            request_timeout: self.request_timeout,
+            channel_capacity: self.channel_capacity,
+            seed: self.seed,
+            read_snapshots: self.read_snapshots,
+            hasher: self.hasher,
+            value: PhantomData,
         }
     }
 
-    async fn set_is_active(&self, client_id: Arc<str>, is_active: bool) {
-        let client = self.get_bucket(&client_id);
-        println!("set_is_active for client_id: {client_id}, value: {is_active}.");
-        let x = self.clients[client].clone();
-        if let Err(e) = x.send(Commands::SetIsActive { client_id, is_active }).await {
-            println!("SendError: {e}");
+    /// Spawns the buckets' event loops, so this must run inside a Tokio
+    /// runtime. Stop them with `GatewayService::shutdown`.
+    pub fn build(self) -> GatewayService<S, V> {
+        let mut service = GatewayService::with_hasher(self.hasher);
+        service.sweep = self.sweep;
+        service.rate_limit = self.rate_limit;
+        service.request_timeout = self.request_timeout;
+        service.channel_capacity = self.channel_capacity;
+        service.seed = self.seed;
+        service.read_snapshots = self.read_snapshots;
+        for _ in 0..self.buckets {
+            service.add_bucket();
         }
+        service
     }
+}
 
-    async fn get_is_active(&self, client_id: Arc<str>) -> bool {
-        let (sender, mut receiver) = channel::<bool>(1);
-        self.send_command(
-            client_id.clone(),
-            Commands::GetIsActive { client_id, sender },
-        )
-        .await;
+impl<S: BuildHasher, V: Clone + Default + Send + Sync + 'static> GatewayService<S, V> {
+
+    /// A service with no buckets that routes client ids using `hasher`.
+    pub fn with_hasher(hasher: S) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        Self {
+            clients: vec![],
+            handles: vec![],
+            sweep: None,
+            rate_limit: None,
+            request_timeout: None,
+            channel_capacity: CHANNEL_CAPACITY,
+            weights: vec![],
+            seed: 0,
+            read_snapshots: false,
+            snapshots: vec![],
+            events,
+            hasher,
+        }
+    }
 
-        match receiver.recv().await {
-            Some(status) => {
-                println!("Status: {status}");
-                status
+    /// How long `get_is_active` waits for a bucket to answer before failing
+    /// with `GatewayError::Timeout`. `None`, the default, waits forever.
+    pub fn set_request_timeout(&mut self, timeout: Option<Duration>) {
+        self.request_timeout = timeout;
+    }
+
+    async fn await_reply<T>(&self, receiver: oneshot::Receiver<T>) -> Result<T, GatewayError> {
+        match self.request_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, receiver).await {
+                Ok(reply) => reply.map_err(|_| GatewayError::RecvFailed),
+                Err(_) => Err(GatewayError::Timeout),
             },
-            None => {
-                eprintln!("Failed to receive is_active status");
-                false
+            None => receiver.await.map_err(|_| GatewayError::RecvFailed),
+        }
+    }
+
+    /// A stream of `is_active` flips from every bucket. A subscriber that
+    /// falls more than `EVENT_CAPACITY` events behind gets
+    /// `RecvError::Lagged(n)` once, then resumes from the oldest retained
+    /// event; it is never disconnected for being slow.
+    pub fn subscribe(&self) -> broadcast::Receiver<StateChange> {
+        self.events.subscribe()
+    }
+
+    fn get_bucket(&self, client_id: &str) -> usize {
+        self.get_bucket_weighted(client_id)
+    }
+
+    /// Jump-hashes onto one virtual node per unit of weight, then maps the
+    /// node back to the bucket owning it, so a bucket of weight 3 gets about
+    /// three times the keys of one of weight 1. With every weight at 1 this
+    /// is plain jump hash over the buckets. A service with no buckets routes
+    /// everything to bucket 0, so its calls fail with
+    /// `GatewayError::BucketNotFound(0)`.
+    pub fn get_bucket_weighted(&self, client_id: &str) -> usize {
+        // spread small seeds over every bit; seed 0 keeps the plain hash
+        let final_hash = self.hasher.hash_one(client_id) ^ self.seed.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        let total: i64 = self.weights.iter().map(|&weight| weight as i64).sum();
+
+        let mut node = jump_hash(final_hash, total);
+        for (bucket, &weight) in self.weights.iter().enumerate() {
+            if node < weight as i64 {
+                return bucket;
             }
+            node -= weight as i64;
         }
+        0
     }
 
-}
+    /// Sets each bucket's share of the keys; new buckets start at weight 1.
+    /// Changing weights remaps some clients, so follow it with `rebalance`.
+    ///
+    /// Panics unless there is exactly one non-zero weight per bucket.
+    pub fn set_weights(&mut self, weights: Vec<u32>) {
+        assert_eq!(weights.len(), self.clients.len(), "need one weight per bucket");
+        assert!(weights.iter().all(|&weight| weight > 0), "weights must be non-zero");
+        self.weights = weights;
+    }
+
+    /// Rotates the key space: services with the same hasher, weights and
+    /// seed map ids identically, while a different seed gives an unrelated
+    /// mapping, e.g. for A/B bucket experiments. Like `set_weights` this
+    /// remaps clients, so follow it with `rebalance`.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// How many of `sample` route to each bucket, for checking how evenly
+    /// ids spread. Every bucket has an entry, including those that got none.
+    pub fn distribution(&self, sample: &[&str]) -> HashMap<usize, usize> {
+        let mut counts: HashMap<usize, usize> = (0..self.clients.len()).map(|bucket| (bucket, 0)).collect();
+        for client_id in sample {
+            *counts.entry(self.get_bucket(client_id)).or_default() += 1;
+        }
+        counts
+    }
+
+    pub fn bucket_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Spawns a new bucket whose event loop owns its own `Gateway`. Going
+    /// from N to N+1 buckets remaps ~1/(N+1) of client ids, all onto the new
+    /// bucket; their existing state stays behind in the bucket they were
+    /// added to.
+    pub fn add_bucket(&mut self) {
+        let (tx, handle, snapshot) = self.spawn_bucket();
+        self.clients.push(tx);
+        self.handles.push(handle);
+        self.weights.push(1);
+        self.snapshots.extend(snapshot);
+    }
+
+    fn spawn_bucket(&self) -> (Sender<Commands<V>>, JoinHandle<()>, Option<Arc<ReadSnapshot>>) {
+        let (tx, rx) = mpsc::channel::<Commands<V>>(self.channel_capacity); // bounded channel for backpressure
+        let mut gateway = Gateway::new(self.rate_limit, self.events.clone());
+        let snapshot = self.read_snapshots.then(|| Arc::new(ReadSnapshot::default()));
+        gateway.read_snapshot = snapshot.clone();
+        (tx, tokio::spawn(event_loop(gateway, rx, self.sweep)), snapshot)
+    }
+
+    /// Respawns every bucket whose event loop has exited, e.g. by panicking,
+    /// with a fresh channel and an empty `Gateway`, and returns their
+    /// indices. The clients a dead bucket held are lost, but its ids are
+    /// reachable again. Call it periodically, or after a `SendFailed`.
+    pub async fn restart_dead_buckets(&mut self) -> Vec<usize> {
+        let mut restarted = vec![];
+        for bucket in 0..self.handles.len() {
+            if !self.handles[bucket].is_finished() {
+                continue;
+            }
+            let (tx, handle, snapshot) = self.spawn_bucket();
+            let dead = std::mem::replace(&mut self.handles[bucket], handle);
+            self.clients[bucket] = tx;
+            if let Some(snapshot) = snapshot {
+                self.snapshots[bucket] = snapshot;
+            }
+            if let Err(e) = dead.await {
+                eprintln!("Bucket {bucket} event loop failed: {e}");
+            }
+            restarted.push(bucket);
+        }
+        restarted
+    }
+
+    /// Closes the last bucket's channel and waits for its event loop to drain
+    /// the queued commands, then moves the clients it held, as `save` would
+    /// record them, into the buckets their ids now map to, replacing any
+    /// stale copy found there. Returns
+    /// false if there was no bucket to remove. Removing the only bucket drops
+    /// its clients, as there is nowhere left to put them.
+    ///
+    /// Fails if the bucket's clients could not be exported, e.g. because its
+    /// event loop had died, or imported, e.g. because a destination bucket is
+    /// draining. The bucket is removed either way, and the clients that did
+    /// not make it across are lost.
+    pub async fn remove_bucket(&mut self) -> Result<bool, GatewayError> {
+        let (Some(sender), Some(handle)) = (self.clients.pop(), self.handles.pop()) else {
+            return Ok(false);
+        };
+        self.weights.pop();
+        self.snapshots.pop();
+        // queued behind everything already sent, so it sees all of it applied
+        let (reply, receiver) = oneshot::channel::<Vec<ClientRecord<V>>>();
+        let sent = sender.send(Commands::Export { sender: reply }).await;
+        drop(sender);
+        if let Err(e) = handle.await {
+            eprintln!("Bucket event loop failed: {e}");
+        }
+        sent.map_err(|_| GatewayError::SendFailed)?;
+        let records = receiver.await.map_err(|_| GatewayError::RecvFailed)?;
+        if self.clients.is_empty() {
+            return Ok(true);
+        }
+
+        let mut by_bucket: Vec<Vec<ClientRecord<V>>> = vec![vec![]; self.clients.len()];
+        for record in records {
+            by_bucket[self.get_bucket(&record.client_id)].push(record);
+        }
+        self.import(by_bucket, true).await?;
+        Ok(true)
+    }
+
+    /// Quiesces `bucket` ahead of removing it: commands already queued run
+    /// as usual, but writes that add or activate clients sent after this fail
+    /// with `GatewayError::Draining`, and heartbeats are ignored. Returns
+    /// once the queued commands are done.
+    /// Reads keep working; a bucket stays draining until it is restarted.
+    pub async fn drain(&self, bucket: usize) -> Result<(), GatewayError> {
+        let (sender, receiver) = oneshot::channel::<()>();
+        self.send_to_bucket(bucket, Commands::Drain { sender }).await?;
+        receiver.await.map_err(|_| GatewayError::RecvFailed)
+    }
+
+    /// Asks every bucket's event loop to stop and waits for all of them to
+    /// exit. Commands already queued ahead of the shutdown are processed.
+    pub async fn shutdown(self) {
+        for sender in &self.clients {
+            // a bucket whose loop already exited has nothing left to stop
+            let _ = sender.send(Commands::Shutdown).await;
+        }
+        for handle in self.handles {
+            if let Err(e) = handle.await {
+                eprintln!("Bucket event loop failed: {e}");
+            }
+        }
+    }
+
+    /// Like `shutdown`, but gives the buckets only `timeout` in total to
+    /// stop. Any bucket still running then, e.g. stuck on a slow command or
+    /// behind a full queue, is aborted, and its index is returned. An aborted
+    /// task stops at its next `.await`.
+    pub async fn shutdown_timeout(self, timeout: Duration) -> Vec<usize> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        for sender in &self.clients {
+            // a full queue must not hold up the deadline
+            let _ = tokio::time::timeout_at(deadline, sender.send(Commands::Shutdown)).await;
+        }
 
-impl Gateway {
-    fn new() -> Self {
-        Self { clients: HashMap::default() }
+        let mut aborted = vec![];
+        for (bucket, mut handle) in self.handles.into_iter().enumerate() {
+            match tokio::time::timeout_at(deadline, &mut handle).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => eprintln!("Bucket {bucket} event loop failed: {e}"),
+                Err(_) => {
+                    handle.abort();
+                    aborted.push(bucket);
+                }
+            }
+        }
+        aborted
     }
 
-    fn add_client(&mut self, client_id: Arc<str>) {
-        self.clients.insert(client_id.to_string(), Client { is_active: false });
+    /// The primary bucket for `client_id` followed by the next `k - 1`
+    /// buckets, wrapping around. Never repeats a bucket, so fewer than `k`
+    /// come back when there are fewer than `k` buckets.
+    pub fn get_buckets(&self, client_id: &str, k: usize) -> Vec<usize> {
+        let buckets = self.clients.len();
+        if buckets == 0 {
+            return vec![];
+        }
+        let primary = self.get_bucket(client_id);
+        (0..k.min(buckets)).map(|i| (primary + i) % buckets).collect()
     }
 
-    fn set_is_active(&mut self, client_id: Arc<str>, is_active: bool) {
-        if let Some(client) = self.clients.get_mut(&client_id.to_string()) {
-            client.is_active = is_active;
+    /// `get_bucket`, failing with `GatewayError::BucketNotFound` instead of
+    /// naming a bucket the service does not have, e.g. when it has none.
+    fn existing_bucket(&self, client_id: &str) -> Result<usize, GatewayError> {
+        let bucket = self.get_bucket(client_id);
+        if bucket < self.clients.len() {
+            Ok(bucket)
+        } else {
+            Err(GatewayError::BucketNotFound(bucket))
         }
     }
 
-    fn get_is_active(&mut self, client_id: Arc<str>) -> bool {
-        let client_id = &client_id.to_string();
+    async fn send_command(&self, client_id: Arc<str>, command: Commands<V>) -> Result<(), GatewayError> {
+        self.send_to_bucket(self.get_bucket(&client_id), command).await
+    }
 
-        if let Some(client) = self.clients.get_mut(client_id) {
-            return client.is_active
+    async fn send_to_bucket(&self, bucket: usize, command: Commands<V>) -> Result<(), GatewayError> {
+        if let Some(sender) = self.clients.get(bucket) {
+            sender.send(command).await.map_err(|_| GatewayError::SendFailed)
         } else {
-            return false
+            Err(GatewayError::BucketNotFound(bucket))
         }
     }
-}
 
-async fn event_loop (gateway: Arc<Mutex<Gateway>>, mut rx: Receiver<Commands>) {
-     while let Some(command) = rx.recv().await {
-        let gateway = gateway.clone();
-        tokio::task::spawn(async move {
-            let mut g = gateway.lock().await;
-            match command {
-                Commands::AddClient { client_id } => g.add_client(client_id),
-                Commands::SetIsActive { client_id, is_active } => g.set_is_active(client_id, is_active),
-                Commands::GetIsActive { client_id, sender } => {
-                    let v = g.get_is_active(client_id);
-                    sender.send(v).await.unwrap();
+    /// Like `send_command`, but fails with `GatewayError::Full` instead of
+    /// waiting when the bucket's queue is saturated.
+    fn try_send_command(&self, client_id: Arc<str>, command: Commands<V>) -> Result<(), GatewayError> {
+        let bucket = self.get_bucket(&client_id);
+        let sender = self.clients.get(bucket).ok_or(GatewayError::BucketNotFound(bucket))?;
+        sender.try_send(command).map_err(|e| match e {
+            TrySendError::Full(_) => GatewayError::Full,
+            TrySendError::Closed(_) => GatewayError::SendFailed,
+        })
+    }
+
+    pub fn try_add_client(&self, client_id: impl Into<Arc<str>>) -> Result<(), GatewayError> {
+        let client_id = client_id.into();
+        self.try_send_command(client_id.clone(), Commands::AddClient { client_id, ack: None })
+    }
+
+    pub fn try_set_is_active(&self, client_id: impl Into<Arc<str>>, is_active: bool) -> Result<(), GatewayError> {
+        let client_id = client_id.into();
+        self.try_send_command(client_id.clone(), Commands::SetState { client_id, state: is_active.into(), ack: None })
+    }
+
+    /// Queues the client and returns without waiting for the bucket; a read
+    /// straight after may not see it yet. Use `add_client_sync` to wait.
+    pub async fn add_client(&self, client_id: impl Into<Arc<str>>) -> Result<(), GatewayError> {
+        let client_id = client_id.into();
+        self.send_command(client_id.clone(), Commands::AddClient { client_id, ack: None }).await
+    }
+
+    /// Like `add_client`, but gives up with `GatewayError::Timeout` if the
+    /// bucket's queue is still full at `deadline`, bounding how long a caller
+    /// waits on a saturated bucket. Once queued the client is added as usual.
+    pub async fn add_client_deadline(&self, client_id: impl Into<Arc<str>>, deadline: tokio::time::Instant) -> Result<(), GatewayError> {
+        let client_id = client_id.into();
+        let command = Commands::AddClient { client_id: client_id.clone(), ack: None };
+        match tokio::time::timeout_at(deadline, self.send_command(client_id, command)).await {
+            Ok(sent) => sent,
+            Err(_) => Err(GatewayError::Timeout),
+        }
+    }
+
+    /// Fire-and-forget, like `add_client`; `set_is_active_sync` waits.
+    pub async fn set_is_active(&self, client_id: impl Into<Arc<str>>, is_active: bool) -> Result<(), GatewayError> {
+        let client_id = client_id.into();
+        self.send_command(client_id.clone(), Commands::SetState { client_id, state: is_active.into(), ack: None }).await
+    }
+
+    /// Like `add_client`, but returns only once the bucket has applied it:
+    /// `true` if the client is new, `false` if it already existed and was
+    /// left as it was.
+    pub async fn add_client_sync(&self, client_id: impl Into<Arc<str>>) -> Result<bool, GatewayError> {
+        let client_id = client_id.into();
+        let (ack, receiver) = oneshot::channel::<Result<bool, GatewayError>>();
+        self.send_command(client_id.clone(), Commands::AddClient { client_id, ack: Some(ack) }).await?;
+        receiver.await.map_err(|_| GatewayError::RecvFailed)?
+    }
+
+    /// Like `set_is_active`, but returns only once the bucket has applied it.
+    pub async fn set_is_active_sync(&self, client_id: impl Into<Arc<str>>, is_active: bool) -> Result<(), GatewayError> {
+        let client_id = client_id.into();
+        let (ack, receiver) = oneshot::channel::<Result<(), GatewayError>>();
+        self.send_command(client_id.clone(), Commands::SetState { client_id, state: is_active.into(), ack: Some(ack) }).await?;
+        receiver.await.map_err(|_| GatewayError::RecvFailed)?
+    }
+
+    /// Like `set_is_active_sync`, with the full `ClientState` rather than a
+    /// flag. `is_active` reads `true` only for `ClientState::Active`.
+    pub async fn set_state_sync(&self, client_id: impl Into<Arc<str>>, state: ClientState) -> Result<(), GatewayError> {
+        let client_id = client_id.into();
+        let (ack, receiver) = oneshot::channel::<Result<(), GatewayError>>();
+        self.send_command(client_id.clone(), Commands::SetState { client_id, state, ack: Some(ack) }).await?;
+        receiver.await.map_err(|_| GatewayError::RecvFailed)?
+    }
+
+    /// Adds the client to each of its `k` buckets from `get_buckets`,
+    /// returning once all of them have applied it.
+    pub async fn add_client_replicated(&self, client_id: impl Into<Arc<str>>, k: usize) -> Result<(), GatewayError> {
+        let client_id = client_id.into();
+        for bucket in self.get_buckets(&client_id, k) {
+            let (ack, receiver) = oneshot::channel::<Result<bool, GatewayError>>();
+            self.send_to_bucket(bucket, Commands::AddClient { client_id: client_id.clone(), ack: Some(ack) }).await?;
+            receiver.await.map_err(|_| GatewayError::RecvFailed)??;
+        }
+        Ok(())
+    }
+
+    /// `set_is_active_sync` on each of the client's `k` buckets.
+    pub async fn set_is_active_replicated(&self, client_id: impl Into<Arc<str>>, is_active: bool, k: usize) -> Result<(), GatewayError> {
+        let client_id = client_id.into();
+        for bucket in self.get_buckets(&client_id, k) {
+            let (ack, receiver) = oneshot::channel::<Result<(), GatewayError>>();
+            let command = Commands::SetState { client_id: client_id.clone(), state: is_active.into(), ack: Some(ack) };
+            self.send_to_bucket(bucket, command).await?;
+            receiver.await.map_err(|_| GatewayError::RecvFailed)??;
+        }
+        Ok(())
+    }
+
+    /// Reads from the primary bucket, falling back to the next replica when
+    /// a bucket's actor is unreachable. Fails with the last error if none of
+    /// the `k` buckets answer.
+    pub async fn get_is_active_replicated(&self, client_id: impl Into<Arc<str>>, k: usize) -> Result<bool, GatewayError> {
+        let client_id = client_id.into();
+        let mut last_err = GatewayError::BucketNotFound(0);
+        for bucket in self.get_buckets(&client_id, k) {
+            let (sender, receiver) = oneshot::channel::<bool>();
+            let command = Commands::GetIsActive { client_id: client_id.clone(), sender };
+            match self.send_to_bucket(bucket, command).await {
+                Ok(()) => match self.await_reply(receiver).await {
+                    Ok(is_active) => return Ok(is_active),
+                    Err(e) => last_err = e,
                 },
+                Err(e) => last_err = e,
             }
-        });
+        }
+        Err(last_err)
     }
-}
 
-pub async fn run() {
-    let gat = Arc::new(Mutex::new(Gateway::new()));
-    let num_buckets = 1;
-    let mut service = GatewayService { clients: Vec::with_capacity(num_buckets) };
+    /// Adds many clients with one message per bucket rather than one per
+    /// client, returning once every bucket has applied its share.
+    pub async fn add_clients<I>(&self, client_ids: I) -> Result<(), GatewayError>
+    where
+        I: IntoIterator,
+        I::Item: Into<Arc<str>>,
+    {
+        let mut by_bucket: Vec<Vec<Arc<str>>> = vec![vec![]; self.clients.len()];
+        for client_id in client_ids {
+            let client_id = client_id.into();
+            by_bucket[self.existing_bucket(&client_id)?].push(client_id);
+        }
 
+        let mut receivers = vec![];
+        for (sender, client_ids) in self.clients.iter().zip(by_bucket) {
+            if client_ids.is_empty() {
+                continue;
+            }
+            let (reply, receiver) = oneshot::channel::<Result<(), GatewayError>>();
+            sender.send(Commands::AddClients { client_ids, sender: reply }).await.map_err(|_| GatewayError::SendFailed)?;
+            receivers.push(receiver);
+        }
+        for receiver in receivers {
+            receiver.await.map_err(|_| GatewayError::RecvFailed)??;
+        }
+        Ok(())
+    }
 
-    for _ in 0..num_buckets {
-    let (tx, rx) = mpsc::channel::<Commands>(1024); // bounded channel for backpressure
-        service.clients.push(tx);
-        tokio::spawn(event_loop(gat.clone(), rx));
+    /// Keeps a client alive: marks it active and resets its expiry clock.
+    pub async fn heartbeat(&self, client_id: impl Into<Arc<str>>) -> Result<(), GatewayError> {
+        let client_id = client_id.into();
+        self.send_command(client_id.clone(), Commands::Heartbeat { client_id }).await
     }
 
-    let cid: Arc<str> = Arc::from("client123");
-    service.add_client(cid.clone()).await;
-    thread::sleep(Duration::from_millis(150));
-    println!("Add Client");
+    pub async fn get_is_active(&self, client_id: impl Into<Arc<str>>) -> Result<bool, GatewayError> {
+        let client_id = client_id.into();
+        let (sender, receiver) = oneshot::channel::<bool>();
+        self.send_command(
+            client_id.clone(),
+            Commands::GetIsActive { client_id, sender },
+        )
+        .await?;
 
-    service.set_is_active(cid.clone(), true).await;
-    thread::sleep(Duration::from_millis(150));
-    println!("Set IsActive");
+        self.await_reply(receiver).await
+    }
 
-    let is_active = service.get_is_active(cid).await;
-    println!("is_active: {is_active}");
-}
+    /// The client's `ClientState`, or `None` if the client is unknown.
+    pub async fn get_state(&self, client_id: impl Into<Arc<str>>) -> Result<Option<ClientState>, GatewayError> {
+        let client_id = client_id.into();
+        let (sender, receiver) = oneshot::channel::<Option<ClientState>>();
+        self.send_command(client_id.clone(), Commands::GetState { client_id, sender }).await?;
+
+        self.await_reply(receiver).await
+    }
+
+    /// Sets `is_active` to `new` only if it is currently `expected`, as one
+    /// step no other command can interleave with, and returns whether it
+    /// did; `false` also for an unknown client. Like `set_is_active_sync` it
+    /// fails with `RateLimited` or `Draining` rather than write.
+    pub async fn compare_and_set(&self, client_id: impl Into<Arc<str>>, expected: bool, new: bool) -> Result<bool, GatewayError> {
+        let client_id = client_id.into();
+        let (sender, receiver) = oneshot::channel::<Result<bool, GatewayError>>();
+        self.send_command(client_id.clone(), Commands::CompareAndSet { client_id, expected, new, sender }).await?;
+
+        self.await_reply(receiver).await?
+    }
+
+    /// Reads `is_active` from the bucket's last published snapshot, without
+    /// messaging the bucket. The snapshot trails the bucket by at most one
+    /// batch of writes, so a write made just before may not show yet; use
+    /// `get_is_active` to read your own writes. `None` if the client is not
+    /// in the snapshot, or the service was built without `read_snapshots`.
+    pub fn get_is_active_snapshot(&self, client_id: &str) -> Option<bool> {
+        let snapshot = self.snapshots.get(self.get_bucket(client_id))?.load();
+        snapshot.get(client_id).copied()
+    }
+
+    /// Reads `is_active`, first adding the client if it is new, in a single
+    /// message so nothing can slip in between. A new client reads `false`.
+    pub async fn get_or_create(&self, client_id: impl Into<Arc<str>>) -> Result<bool, GatewayError> {
+        let client_id = client_id.into();
+        let (sender, receiver) = oneshot::channel::<Result<bool, GatewayError>>();
+        self.send_command(client_id.clone(), Commands::GetOrCreate { client_id, sender }).await?;
+
+        self.await_reply(receiver).await?
+    }
+
+    pub async fn remove_client(&self, client_id: impl Into<Arc<str>>) -> Result<bool, GatewayError> {
+        let client_id = client_id.into();
+        let (sender, receiver) = oneshot::channel::<bool>();
+        self.send_command(
+            client_id.clone(),
+            Commands::RemoveClient { client_id, sender },
+        )
+        .await?;
+
+        receiver.await.map_err(|_| GatewayError::RecvFailed)
+    }
+
+    /// Sets a metadata entry on an existing client, replacing any previous
+    /// value. Returns `false` if the client is unknown.
+    pub async fn set_meta(&self, client_id: impl Into<Arc<str>>, key: impl Into<String>, value: impl Into<String>) -> Result<bool, GatewayError> {
+        let client_id = client_id.into();
+        let (sender, receiver) = oneshot::channel::<Result<bool, GatewayError>>();
+        let (key, value) = (key.into(), value.into());
+        self.send_command(client_id.clone(), Commands::SetMeta { client_id, key, value, sender }).await?;
+
+        receiver.await.map_err(|_| GatewayError::RecvFailed)?
+    }
 
-// --------------------- Jump Hash Placeholder ---------------------
-fn jump_hash(hash: u64, buckets: i64) -> i64 {
-    // Replace with your actual jump hash implementation
-    (hash % buckets as u64) as i64
-}
\ No newline at end of file
+    /// The metadata value stored under `key`, if the client and key exist.
+    pub async fn get_meta(&self, client_id: impl Into<Arc<str>>, key: impl Into<String>) -> Result<Option<String>, GatewayError> {
+        let client_id = client_id.into();
+        let (sender, receiver) = oneshot::channel::<Option<String>>();
+        let key = key.into();
+        self.send_command(client_id.clone(), Commands::GetMeta { client_id, key, sender }).await?;
+
+        receiver.await.map_err(|_| GatewayError::RecvFailed)
+    }
+
+    /// Replaces the client's value. Returns `false` if the client is unknown.
+    pub async fn set_value(&self, client_id: impl Into<Arc<str>>, value: V) -> Result<bool, GatewayError> {
+        let client_id = client_id.into();
+        let (sender, receiver) = oneshot::channel::<Result<bool, GatewayError>>();
+        self.send_command(client_id.clone(), Commands::SetValue { client_id, value, sender }).await?;
+
+        receiver.await.map_err(|_| GatewayError::RecvFailed)?
+    }
+
+    /// A copy of the client's value, or `None` if the client is unknown.
+    pub async fn get_value(&self, client_id: impl Into<Arc<str>>) -> Result<Option<V>, GatewayError> {
+        let client_id = client_id.into();
+        let (sender, receiver) = oneshot::channel::<Option<V>>();
+        self.send_command(client_id.clone(), Commands::GetValue { client_id, sender }).await?;
+
+        self.await_reply(receiver).await
+    }
+
+    /// Returns once every bucket has processed all the commands sent to it
+    /// before this call, including fire-and-forget ones such as `add_client`.
+    /// Commands sent concurrently with it may or may not be included.
+    pub async fn wait_idle(&self) -> Result<(), GatewayError> {
+        self.fan_out(|sender| Commands::Ping { sender }).await?;
+        Ok(())
+    }
+
+    /// Pings every bucket and reports, in bucket order, which answered within
+    /// `PING_TIMEOUT`. A bucket whose task has stopped or panicked, or whose
+    /// queue is too backed up to get to the ping in time, reports `false`.
+    pub async fn ping_all(&self) -> Vec<bool> {
+        let deadline = tokio::time::Instant::now() + PING_TIMEOUT;
+        let mut receivers = Vec::with_capacity(self.clients.len());
+        for sender in &self.clients {
+            let (reply, receiver) = oneshot::channel::<()>();
+            let sent = matches!(tokio::time::timeout_at(deadline, sender.send(Commands::Ping { sender: reply })).await, Ok(Ok(())));
+            receivers.push(sent.then_some(receiver));
+        }
+
+        let mut alive = Vec::with_capacity(receivers.len());
+        for receiver in receivers {
+            alive.push(match receiver {
+                Some(receiver) => matches!(tokio::time::timeout_at(deadline, receiver).await, Ok(Ok(()))),
+                None => false,
+            });
+        }
+        alive
+    }
+
+    /// Sends a command built by `command` to every bucket and collects one
+    /// reply per bucket, in bucket order.
+    async fn fan_out<T>(&self, command: impl Fn(oneshot::Sender<T>) -> Commands<V>) -> Result<Vec<T>, GatewayError> {
+        let mut receivers = Vec::with_capacity(self.clients.len());
+        for sender in &self.clients {
+            let (reply, receiver) = oneshot::channel::<T>();
+            sender.send(command(reply)).await.map_err(|_| GatewayError::SendFailed)?;
+            receivers.push(receiver);
+        }
+
+        let mut replies = Vec::with_capacity(receivers.len());
+        for receiver in receivers {
+            replies.push(receiver.await.map_err(|_| GatewayError::RecvFailed)?);
+        }
+        Ok(replies)
+    }
+
+    /// Sets `is_active` on every client in every bucket, returning once all
+    /// buckets have acknowledged. Draining buckets are left as they are and
+    /// make this fail with `GatewayError::Draining`; the others still apply it.
+    pub async fn broadcast_set_is_active(&self, is_active: bool) -> Result<(), GatewayError> {
+        self.fan_out(|sender| Commands::SetAllActive { is_active, sender }).await?.into_iter().collect()
+    }
+
+    /// Evicts clients not seen for `ttl` from every bucket and returns their
+    /// ids, in no particular order.
+    pub async fn sweep_expired(&self, ttl: Duration) -> Result<Vec<String>, GatewayError> {
+        let expired = self.fan_out(|sender| Commands::Sweep { ttl, sender }).await?;
+        Ok(expired.into_iter().flatten().collect())
+    }
+
+    /// Ids of every active client. Buckets answer independently, so the
+    /// order is unspecified.
+    pub async fn list_active_clients(&self) -> Result<Vec<String>, GatewayError> {
+        let active = self.fan_out(|sender| Commands::ListActive { sender }).await?;
+        Ok(active.into_iter().flatten().collect())
+    }
+
+    /// Writes every client in every bucket to `path`: ids, `is_active`, values
+    /// and metadata. Commands processed while saving may or may not be included.
+    pub async fn save(&self, path: impl AsRef<Path>) -> io::Result<()>
+    where
+        V: Encode,
+    {
+        let buckets = self.fan_out(|sender| Commands::Export { sender }).await.map_err(io::Error::other)?;
+        GatewaySnapshot { clients: buckets.into_iter().flatten().collect() }.save(path.as_ref())
+    }
+
+    /// Restores clients written by `save`, routing each to the bucket its id
+    /// maps to now, so the bucket count may differ from when it was saved.
+    pub async fn load(&self, path: impl AsRef<Path>) -> io::Result<()>
+    where
+        V: Decode<()>,
+    {
+        let snapshot = GatewaySnapshot::load(path.as_ref())?;
+        let mut by_bucket: Vec<Vec<ClientRecord<V>>> = vec![vec![]; self.clients.len()];
+        for record in snapshot.clients {
+            by_bucket[self.existing_bucket(&record.client_id).map_err(io::Error::other)?].push(record);
+        }
+
+        self.import(by_bucket, true).await.map_err(io::Error::other)
+    }
+
+    /// Sends `by_bucket[i]` to bucket `i` and waits for every bucket to
+    /// apply its share.
+    async fn import(&self, by_bucket: Vec<Vec<ClientRecord<V>>>, overwrite: bool) -> Result<(), GatewayError> {
+        let mut receivers = vec![];
+        for (sender, records) in self.clients.iter().zip(by_bucket) {
+            if records.is_empty() {
+                continue;
+            }
+            let (reply, receiver) = oneshot::channel::<Result<(), GatewayError>>();
+            sender.send(Commands::Import { records, overwrite, sender: reply }).await.map_err(|_| GatewayError::SendFailed)?;
+            receivers.push(receiver);
+        }
+        for receiver in receivers {
+            receiver.await.map_err(|_| GatewayError::RecvFailed)??;
+        }
+        Ok(())
+    }
+
+    /// After the bucket count changed from `old_count`, moves every client
+    /// whose id now maps elsewhere out of buckets `0..old_count` and into its
+    /// new bucket, so reads find it again. Returns how many moved. A client
+    /// already present in its new bucket, e.g. re-added since the resize,
+    /// keeps that newer state.
+    pub async fn rebalance(&self, old_count: usize) -> Result<usize, GatewayError> {
+        let mut by_bucket: Vec<Vec<ClientRecord<V>>> = vec![vec![]; self.clients.len()];
+        let mut moved = 0;
+        for bucket in 0..old_count.min(self.clients.len()) {
+            let (sender, receiver) = oneshot::channel::<Vec<ClientRecord<V>>>();
+            self.send_to_bucket(bucket, Commands::Export { sender }).await?;
+            let client_ids: Vec<Arc<str>> = receiver
+                .await
+                .map_err(|_| GatewayError::RecvFailed)?
+                .into_iter()
+                .filter(|record| self.get_bucket(&record.client_id) != bucket)
+                .map(|record| Arc::from(record.client_id))
+                .collect();
+            if client_ids.is_empty() {
+                continue;
+            }
+
+            let (sender, receiver) = oneshot::channel::<Vec<ClientRecord<V>>>();
+            self.send_to_bucket(bucket, Commands::Extract { client_ids, sender }).await?;
+            for record in receiver.await.map_err(|_| GatewayError::RecvFailed)? {
+                by_bucket[self.get_bucket(&record.client_id)].push(record);
+                moved += 1;
+            }
+        }
+        self.import(by_bucket, false).await?;
+        Ok(moved)
+    }
+
+    /// Every client's `(client_id, is_active)` across all buckets, sorted by
+    /// id. Meant for tests and admin tooling; it copies the whole state.
+    pub async fn snapshot(&self) -> Result<Vec<(String, bool)>, GatewayError> {
+        let buckets = self.fan_out(|sender| Commands::Snapshot { sender }).await?;
+        let mut snapshot: Vec<(String, bool)> = buckets.into_iter().flatten().collect();
+        snapshot.sort();
+        Ok(snapshot)
+    }
+
+    /// Every client's `(client_id, is_active)` across all buckets, in no
+    /// particular order. Unlike `snapshot` nothing is copied up front: each
+    /// bucket sends `chunk_size` entries at a time as the stream is read.
+    /// A bucket handles nothing else until its part has been read, so drain
+    /// the stream promptly, or drop it to let the buckets go.
+    ///
+    /// Panics if `chunk_size` is zero.
+    pub async fn scan(&self, chunk_size: usize) -> Result<impl Stream<Item = (String, bool)>, GatewayError> {
+        assert!(chunk_size > 0, "chunk size must be non-zero");
+        let mut buckets = Vec::with_capacity(self.clients.len());
+        for sender in &self.clients {
+            let (chunks, mut receiver) = mpsc::channel::<Vec<(String, bool)>>(1);
+            sender.send(Commands::Scan { chunk_size, sender: chunks }).await.map_err(|_| GatewayError::SendFailed)?;
+            buckets.push(stream::poll_fn(move |cx| receiver.poll_recv(cx)));
+        }
+        Ok(stream::select_all(buckets).flat_map(stream::iter))
+    }
+
+    /// One `BucketStats` per bucket, in bucket order.
+    pub async fn stats(&self) -> Result<Vec<BucketStats>, GatewayError> {
+        let mut stats = self.fan_out(|sender| Commands::Stats { sender }).await?;
+        for (stats, sender) in stats.iter_mut().zip(&self.clients) {
+            stats.queue_capacity = sender.capacity();
+            stats.max_capacity = sender.max_capacity();
+        }
+        Ok(stats)
+    }
+
+    /// `stats` rendered in the Prometheus text exposition format, one series
+    /// per bucket under a `bucket` label, ready to serve from a scrape
+    /// endpoint.
+    pub async fn metrics_text(&self) -> Result<String, GatewayError> {
+        let stats = self.stats().await?;
+        let per_bucket = |value: fn(&BucketStats) -> u64| {
+            stats.iter().enumerate().map(move |(bucket, stats)| (format!("bucket=\"{bucket}\""), value(stats)))
+        };
+        let mut out = String::new();
+        write_metric(&mut out, "gateway_commands_total", "counter", "Commands processed by the bucket.", per_bucket(|stats| stats.commands_received));
+        write_metric(
+            &mut out,
+            "gateway_commands_by_type_total",
+            "counter",
+            "Commands processed by the bucket, by command.",
+            stats.iter().enumerate().flat_map(|(bucket, stats)| {
+                let mut by_type: Vec<_> = stats.commands_by_type.iter().collect();
+                by_type.sort();
+                by_type
+                    .into_iter()
+                    .map(move |(command, &count)| (format!("bucket=\"{bucket}\",command=\"{command}\""), count))
+            }),
+        );
+        write_metric(&mut out, "gateway_clients_total", "gauge", "Clients held by the bucket.", per_bucket(|stats| stats.client_count as u64));
+        write_metric(
+            &mut out,
+            "gateway_queue_depth",
+            "gauge",
+            "Commands waiting in the bucket's queue.",
+            per_bucket(|stats| (stats.max_capacity - stats.queue_capacity) as u64),
+        );
+        Ok(out)
+    }
+
+    /// Number of clients across all buckets.
+    pub async fn total_client_count(&self) -> Result<usize, GatewayError> {
+        let counts = self.fan_out(|sender| Commands::Count { sender }).await?;
+        Ok(counts.into_iter().sum())
+    }
+
+}
+
+impl<V: Clone + Default> Gateway<V> {
+    fn new(rate_limit: Option<RateLimit>, events: broadcast::Sender<StateChange>) -> Self {
+        Self { clients: HashMap::default(), rate_limit, events, read_snapshot: None }
+    }
+
+    /// Replaces the published read snapshot with the current flags.
+    fn publish_snapshot(&self) {
+        if let Some(snapshot) = &self.read_snapshot {
+            snapshot.store(self.clients.iter().map(|(client_id, client)| (client_id.clone(), client.is_active())).collect());
+        }
+    }
+
+    /// Having no subscribers is not an error; the event is simply dropped.
+    fn emit(&self, client_id: Arc<str>, is_active: bool) {
+        let _ = self.events.send(StateChange { client_id, is_active });
+    }
+
+    /// Adds the client unless it already exists, in which case its state is
+    /// kept and only `last_seen` is refreshed, so a reconnect loses nothing.
+    /// Returns whether the client is new.
+    fn add_client(&mut self, client_id: Arc<str>) -> bool {
+        let now = Instant::now();
+        if let Some(client) = self.clients.get_mut(&*client_id) {
+            client.last_seen = now;
+            return false;
+        }
+        self.clients.insert(ClientId::from(client_id), self.client_builder().build());
+        true
+    }
+
+    /// A `Client::builder` whose token bucket starts full under this
+    /// gateway's rate limit.
+    fn client_builder(&self) -> ClientBuilder<V> {
+        Client::builder().tokens(self.rate_limit.map_or(0.0, |limit| limit.burst as f64))
+    }
+
+    /// Takes one token from the client's bucket, refilling it first. Unknown
+    /// clients are always allowed through; the command is a no-op for them.
+    fn allow(&mut self, client_id: &str) -> bool {
+        let (Some(limit), Some(client)) = (self.rate_limit, self.clients.get_mut(client_id)) else {
+            return true;
+        };
+        let now = Instant::now();
+        let refill = now.duration_since(client.last_refill).as_secs_f64() * limit.per_second;
+        client.tokens = (client.tokens + refill).min(limit.burst as f64);
+        client.last_refill = now;
+        if client.tokens >= 1.0 {
+            client.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Subscribers hear only of changes that flip `is_active`, not of moves
+    /// between the inactive states.
+    fn set_state(&mut self, client_id: Arc<str>, state: ClientState) {
+        if let Some(client) = self.clients.get_mut(&*client_id) {
+            let flipped = client.is_active() != state.is_active();
+            client.state = state;
+            client.last_seen = Instant::now();
+            if flipped {
+                self.emit(client_id, state.is_active());
+            }
+        }
+    }
+
+    /// `set_state` to `new`, but only if the client's `is_active` is `expected`.
+    fn compare_and_set(&mut self, client_id: Arc<str>, expected: bool, new: bool) -> bool {
+        match self.clients.get(&*client_id) {
+            Some(client) if client.is_active() == expected => {
+                self.set_state(client_id, new.into());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn set_meta(&mut self, client_id: Arc<str>, key: String, value: String) -> bool {
+        if let Some(client) = self.clients.get_mut(&*client_id) {
+            client.metadata.insert(key, value);
+            client.last_seen = Instant::now();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn get_meta(&self, client_id: Arc<str>, key: &str) -> Option<String> {
+        self.clients.get(&*client_id)?.metadata.get(key).cloned()
+    }
+
+    fn set_value(&mut self, client_id: Arc<str>, value: V) -> bool {
+        if let Some(client) = self.clients.get_mut(&*client_id) {
+            client.value = value;
+            client.last_seen = Instant::now();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn get_value(&self, client_id: Arc<str>) -> Option<V> {
+        Some(self.clients.get(&*client_id)?.value.clone())
+    }
+
+    fn heartbeat(&mut self, client_id: Arc<str>) {
+        self.set_state(client_id, ClientState::Active);
+    }
+
+    fn remove_client(&mut self, client_id: Arc<str>) -> bool {
+        self.clients.remove(&*client_id).is_some()
+    }
+
+    fn sweep_expired(&mut self, ttl: Duration) -> Vec<String> {
+        let now = Instant::now();
+        let mut expired = vec![];
+        self.clients.retain(|client_id, client| {
+            let keep = now.duration_since(client.last_seen) <= ttl;
+            if !keep {
+                expired.push(client_id.to_string());
+            }
+            keep
+        });
+        expired
+    }
+
+    fn active_clients(&self) -> Vec<String> {
+        self.clients
+            .iter()
+            .filter(|(_, client)| client.is_active())
+            .map(|(client_id, _)| client_id.to_string())
+            .collect()
+    }
+
+    fn export(&self) -> Vec<ClientRecord<V>> {
+        self.clients
+            .iter()
+            .map(|(client_id, client)| ClientRecord {
+                client_id: client_id.to_string(),
+                is_active: client.is_active(),
+                value: client.value.clone(),
+                metadata: client.metadata.clone(),
+            })
+            .collect()
+    }
+
+    fn import(&mut self, records: Vec<ClientRecord<V>>, overwrite: bool) {
+        for record in records {
+            let client_id: Arc<str> = Arc::from(record.client_id);
+            if !overwrite && self.clients.contains_key(&*client_id) {
+                continue;
+            }
+            let client = self.client_builder().state(record.is_active.into()).value(record.value).metadata(record.metadata).build();
+            self.clients.insert(ClientId::from(client_id), client);
+        }
+    }
+
+    fn extract(&mut self, client_ids: Vec<Arc<str>>) -> Vec<ClientRecord<V>> {
+        client_ids
+            .into_iter()
+            .filter_map(|client_id| {
+                let client = self.clients.remove(&*client_id)?;
+                Some(ClientRecord {
+                    client_id: client_id.to_string(),
+                    is_active: client.is_active(),
+                    value: client.value,
+                    metadata: client.metadata,
+                })
+            })
+            .collect()
+    }
+
+    fn snapshot(&self) -> Vec<(String, bool)> {
+        self.clients
+            .iter()
+            .map(|(client_id, client)| (client_id.to_string(), client.is_active()))
+            .collect()
+    }
+
+    fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Each client's id and `is_active`, in no particular order.
+    fn iter(&self) -> impl Iterator<Item = (&str, bool)> {
+        self.clients.iter().map(|(client_id, client)| (client_id.as_str(), client.is_active()))
+    }
+
+    fn set_all_active(&mut self, is_active: bool) {
+        for (client_id, client) in self.clients.iter_mut() {
+            if client.is_active() != is_active {
+                client.state = is_active.into();
+                let _ = self.events.send(StateChange { client_id: client_id.to_arc(), is_active });
+            }
+        }
+    }
+
+    fn get_is_active(&self, client_id: Arc<str>) -> bool {
+        self.clients.get(&*client_id).is_some_and(|client| client.is_active())
+    }
+
+    fn get_state(&self, client_id: Arc<str>) -> Option<ClientState> {
+        Some(self.clients.get(&*client_id)?.state)
+    }
+}
+
+/// Renders a `GatewayService::distribution` as one bar per bucket, scaled so
+/// the largest bucket fills `width` columns:
+///
+/// ```text
+/// bucket 0 | ################################        512
+/// bucket 1 | ######################################## 640
+/// ```
+pub fn render_distribution(counts: &HashMap<usize, usize>, width: usize) -> String {
+    let mut buckets: Vec<(&usize, &usize)> = counts.iter().collect();
+    buckets.sort();
+    let max = counts.values().copied().max().unwrap_or(0).max(1);
+    let label_width = buckets.last().map_or(1, |(bucket, _)| bucket.to_string().len());
+    let mut out = String::new();
+    for (bucket, &count) in buckets {
+        let bar = "#".repeat(count * width / max);
+        out.push_str(&format!("bucket {bucket:>label_width$} | {bar:<width$} {count}\n"));
+    }
+    out
+}
+
+/// Appends one Prometheus metric family: its `# HELP` and `# TYPE` lines,
+/// then a `name{labels} value` line per sample.
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, samples: impl IntoIterator<Item = (String, u64)>) {
+    use std::fmt::Write;
+
+    // writing to a String cannot fail
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{name}{{{labels}}} {value}");
+    }
+}
+
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Owns one bucket's `Gateway` and applies its commands one at a time, inline,
+/// in the order the channel delivers them. Nothing is spawned per command, so
+/// commands from one sender, and hence every command for one client from one
+/// task, take effect in the order they were sent.
+async fn event_loop<V: Clone + Default>(mut gateway: Gateway<V>, mut rx: Receiver<Commands<V>>, sweep: Option<SweepConfig>) {
+    let mut interval = sweep.map(|sweep| tokio::time::interval(sweep.every));
+    let mut stats = BucketStats::default();
+    let mut draining = false;
+    // flag-changing commands since the read snapshot was last published
+    let mut unpublished = 0;
+    loop {
+        let command = tokio::select! {
+            command = rx.recv() => match command {
+                Some(command) => command,
+                None => break,
+            },
+            _ = tick(&mut interval) => {
+                if let Some(sweep) = sweep {
+                    if !gateway.sweep_expired(sweep.ttl).is_empty() {
+                        unpublished += 1;
+                        publish_if_caught_up(&gateway, &rx, &mut unpublished);
+                    }
+                }
+                continue;
+            }
+        };
+
+        let name = command.name();
+        if command.changes_flags() {
+            unpublished += 1;
+        }
+        match command {
+            Commands::AddClient { client_id, ack } => {
+                let added = if draining { Err(GatewayError::Draining) } else { Ok(gateway.add_client(client_id)) };
+                if let Some(ack) = ack {
+                    if ack.send(added).is_err() {
+                        eprintln!("AddClient receiver dropped");
+                    }
+                }
+            },
+            Commands::AddClients { client_ids, sender } => {
+                let result = if draining {
+                    Err(GatewayError::Draining)
+                } else {
+                    for client_id in client_ids {
+                        gateway.add_client(client_id);
+                    }
+                    Ok(())
+                };
+                if sender.send(result).is_err() {
+                    eprintln!("AddClients receiver dropped");
+                }
+            },
+            Commands::SetState { client_id, state, ack } => {
+                let result = if draining {
+                    Err(GatewayError::Draining)
+                } else if gateway.allow(&client_id) {
+                    gateway.set_state(client_id, state);
+                    Ok(())
+                } else {
+                    Err(GatewayError::RateLimited)
+                };
+                if let Some(ack) = ack {
+                    if ack.send(result).is_err() {
+                        eprintln!("SetState receiver dropped");
+                    }
+                }
+            },
+            Commands::GetIsActive { client_id, sender } => {
+                let v = gateway.get_is_active(client_id);
+                if sender.send(v).is_err() {
+                    eprintln!("GetIsActive receiver dropped");
+                }
+            },
+            Commands::GetState { client_id, sender } => {
+                if sender.send(gateway.get_state(client_id)).is_err() {
+                    eprintln!("GetState receiver dropped");
+                }
+            },
+            Commands::CompareAndSet { client_id, expected, new, sender } => {
+                let swapped = if draining {
+                    Err(GatewayError::Draining)
+                } else if gateway.allow(&client_id) {
+                    Ok(gateway.compare_and_set(client_id, expected, new))
+                } else {
+                    Err(GatewayError::RateLimited)
+                };
+                if sender.send(swapped).is_err() {
+                    eprintln!("CompareAndSet receiver dropped");
+                }
+            },
+            Commands::GetOrCreate { client_id, sender } => {
+                let result = if draining {
+                    Err(GatewayError::Draining)
+                } else {
+                    gateway.add_client(client_id.clone());
+                    Ok(gateway.get_is_active(client_id))
+                };
+                if sender.send(result).is_err() {
+                    eprintln!("GetOrCreate receiver dropped");
+                }
+            },
+            Commands::RemoveClient { client_id, sender } => {
+                let removed = gateway.remove_client(client_id);
+                if sender.send(removed).is_err() {
+                    eprintln!("RemoveClient receiver dropped");
+                }
+            },
+            Commands::SetAllActive { is_active, sender } => {
+                let result = if draining {
+                    Err(GatewayError::Draining)
+                } else {
+                    gateway.set_all_active(is_active);
+                    Ok(())
+                };
+                if sender.send(result).is_err() {
+                    eprintln!("SetAllActive receiver dropped");
+                }
+            },
+            Commands::Count { sender } => {
+                if sender.send(gateway.client_count()).is_err() {
+                    eprintln!("Count receiver dropped");
+                }
+            },
+            Commands::Sweep { ttl, sender } => {
+                if sender.send(gateway.sweep_expired(ttl)).is_err() {
+                    eprintln!("Sweep receiver dropped");
+                }
+            },
+            Commands::ListActive { sender } => {
+                if sender.send(gateway.active_clients()).is_err() {
+                    eprintln!("ListActive receiver dropped");
+                }
+            },
+            Commands::SetMeta { client_id, key, value, sender } => {
+                let result = if gateway.allow(&client_id) {
+                    Ok(gateway.set_meta(client_id, key, value))
+                } else {
+                    Err(GatewayError::RateLimited)
+                };
+                if sender.send(result).is_err() {
+                    eprintln!("SetMeta receiver dropped");
+                }
+            },
+            Commands::GetMeta { client_id, key, sender } => {
+                if sender.send(gateway.get_meta(client_id, &key)).is_err() {
+                    eprintln!("GetMeta receiver dropped");
+                }
+            },
+            Commands::SetValue { client_id, value, sender } => {
+                let result = if gateway.allow(&client_id) {
+                    Ok(gateway.set_value(client_id, value))
+                } else {
+                    Err(GatewayError::RateLimited)
+                };
+                if sender.send(result).is_err() {
+                    eprintln!("SetValue receiver dropped");
+                }
+            },
+            Commands::GetValue { client_id, sender } => {
+                if sender.send(gateway.get_value(client_id)).is_err() {
+                    eprintln!("GetValue receiver dropped");
+                }
+            },
+            Commands::Heartbeat { client_id } => {
+                if !draining && gateway.allow(&client_id) {
+                    gateway.heartbeat(client_id);
+                }
+            },
+            Commands::Export { sender } => {
+                if sender.send(gateway.export()).is_err() {
+                    eprintln!("Export receiver dropped");
+                }
+            },
+            Commands::Import { records, overwrite, sender } => {
+                let result = if draining {
+                    Err(GatewayError::Draining)
+                } else {
+                    gateway.import(records, overwrite);
+                    Ok(())
+                };
+                if sender.send(result).is_err() {
+                    eprintln!("Import receiver dropped");
+                }
+            },
+            Commands::Extract { client_ids, sender } => {
+                if sender.send(gateway.extract(client_ids)).is_err() {
+                    eprintln!("Extract receiver dropped");
+                }
+            },
+            Commands::Scan { chunk_size, sender } => {
+                let mut entries = gateway.iter();
+                loop {
+                    let chunk: Vec<(String, bool)> =
+                        entries.by_ref().take(chunk_size).map(|(client_id, is_active)| (client_id.to_string(), is_active)).collect();
+                    // an empty chunk means the scan is done; a send error, that the reader is gone
+                    if chunk.is_empty() || sender.send(chunk).await.is_err() {
+                        break;
+                    }
+                }
+            },
+            Commands::Snapshot { sender } => {
+                if sender.send(gateway.snapshot()).is_err() {
+                    eprintln!("Snapshot receiver dropped");
+                }
+            },
+            Commands::Stats { sender } => {
+                stats.client_count = gateway.client_count();
+                if sender.send(stats.clone()).is_err() {
+                    eprintln!("Stats receiver dropped");
+                }
+            },
+            Commands::Drain { sender } => {
+                draining = true;
+                if sender.send(()).is_err() {
+                    eprintln!("Drain receiver dropped");
+                }
+            },
+            Commands::Ping { sender } => {
+                let _ = sender.send(());
+            },
+            Commands::Shutdown => break,
+        }
+        stats.commands_received += 1;
+        *stats.commands_by_type.entry(name).or_default() += 1;
+        publish_if_caught_up(&gateway, &rx, &mut unpublished);
+    }
+}
+
+/// Publishes the read snapshot once a batch of writes is done: when nothing
+/// else is queued, or after `SNAPSHOT_MAX_BATCH` writes under constant load.
+fn publish_if_caught_up<V: Clone + Default>(gateway: &Gateway<V>, rx: &Receiver<Commands<V>>, unpublished: &mut usize) {
+    if *unpublished > 0 && (rx.is_empty() || *unpublished >= SNAPSHOT_MAX_BATCH) {
+        gateway.publish_snapshot();
+        *unpublished = 0;
+    }
+}
+
+pub async fn run() {
+    let mut service = GatewayService::new(1);
+
+    let cid: Arc<str> = Arc::from("client123");
+    if let Err(e) = service.add_client_sync(cid.clone()).await {
+        eprintln!("add_client failed: {e}");
+    }
+    println!("Add Client");
+
+    if let Err(e) = service.set_is_active_sync(cid.clone(), true).await {
+        eprintln!("set_is_active failed: {e}");
+    }
+    println!("Set IsActive");
+
+    match service.get_is_active(cid.clone()).await {
+        Ok(is_active) => println!("is_active: {is_active}"),
+        Err(e) => eprintln!("get_is_active failed: {e}"),
+    }
+
+    match service.remove_client(cid).await {
+        Ok(removed) => println!("removed: {removed}"),
+        Err(e) => eprintln!("remove_client failed: {e}"),
+    }
+
+    service.add_bucket();
+    println!("buckets: {}", service.bucket_count());
+    if let Err(e) = service.remove_bucket().await {
+        eprintln!("remove_bucket failed: {e}");
+    }
+    println!("buckets: {}", service.bucket_count());
+
+    let aborted = service.shutdown_timeout(Duration::from_secs(5)).await;
+    if !aborted.is_empty() {
+        eprintln!("aborted buckets {aborted:?}");
+    }
+    println!("Shutdown");
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn raw_service(clients: Vec<Sender<Commands>>, handles: Vec<JoinHandle<()>>) -> GatewayService {
+        let mut service = GatewayService::with_hasher(DefaultBuildHasher::default());
+        service.weights = vec![1; clients.len()];
+        service.clients = clients;
+        service.handles = handles;
+        service
+    }
+
+    fn service(num_buckets: usize) -> GatewayService {
+        GatewayService::new(num_buckets)
+    }
+
+    fn client_ids(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("client{i}")).collect()
+    }
+
+    /// FNV-1a, a hasher whose output is fixed by its definition.
+    #[derive(Default)]
+    struct Fnv(u64);
+
+    impl std::hash::Hasher for Fnv {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            for byte in bytes {
+                self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+            }
+        }
+    }
+
+    #[derive(Clone, Copy)]
+    struct FnvBuilder;
+
+    impl BuildHasher for FnvBuilder {
+        type Hasher = Fnv;
+
+        fn build_hasher(&self) -> Fnv {
+            Fnv(0xcbf29ce484222325)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_hasher_is_stable_across_services() {
+        let first = GatewayService::builder().buckets(8).hasher(FnvBuilder).build();
+        let second = GatewayService::builder().buckets(8).hasher(FnvBuilder).build();
+
+        let ids = client_ids(1000);
+        for id in &ids {
+            assert_eq!(first.get_bucket(id), second.get_bucket(id));
+        }
+        // the id still lands in a real bucket and round-trips
+        let cid: Arc<str> = Arc::from("client123");
+        first.add_client_sync(cid.clone()).await.unwrap();
+        first.set_is_active_sync(cid.clone(), true).await.unwrap();
+        assert!(first.get_is_active(cid).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_weighted_buckets_split_proportionally() {
+        let mut service = service(2);
+        service.set_weights(vec![1, 3]);
+        let ids = client_ids(10_000);
+        let in_second = ids.iter().filter(|id| service.get_bucket_weighted(id) == 1).count();
+        let share = in_second as f64 / ids.len() as f64;
+        assert!((share - 0.75).abs() < 0.03, "share {share}");
+    }
+
+    #[tokio::test]
+    async fn test_unit_weights_match_plain_jump_hash() {
+        let service = service(7);
+        for id in client_ids(1000) {
+            let plain = jump_hash(service.hasher.hash_one(&id), 7) as usize;
+            assert_eq!(service.get_bucket_weighted(&id), plain);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_seed_rotates_mapping() {
+        let seeded = |seed| GatewayService::builder().buckets(8).seed(seed).build();
+        let (a, b, c) = (seeded(42), seeded(42), seeded(43));
+        let ids = client_ids(1000);
+        let mapping = |service: &GatewayService| ids.iter().map(|id| service.get_bucket(id)).collect::<Vec<_>>();
+        assert_eq!(mapping(&a), mapping(&b));
+        let moved = mapping(&a).iter().zip(mapping(&c)).filter(|(x, y)| **x != *y).count();
+        // an unrelated mapping moves about 7/8 of the ids
+        assert!(moved > 750, "{moved}");
+
+        // seed 0 is the unseeded mapping
+        let mut plain = service(8);
+        assert_eq!(mapping(&seeded(0)), mapping(&plain));
+        plain.set_seed(42);
+        assert_eq!(mapping(&plain), mapping(&a));
+    }
+
+    #[tokio::test]
+    async fn test_distribution_is_even() {
+        let service = service(8);
+        let ids = client_ids(8_000);
+        let sample: Vec<&str> = ids.iter().map(String::as_str).collect();
+        let counts = service.distribution(&sample);
+        assert_eq!(counts.len(), 8);
+        assert_eq!(counts.values().sum::<usize>(), 8_000);
+        let (min, max) = (*counts.values().min().unwrap(), *counts.values().max().unwrap());
+        assert!(max * 4 < min * 5, "max {max} and min {min} differ by over 25%");
+
+        // buckets nothing routes to still show up
+        assert_eq!(service.distribution(&[]), (0..8).map(|bucket| (bucket, 0)).collect());
+    }
+
+    #[test]
+    fn test_render_distribution() {
+        let counts = HashMap::from([(0, 5), (1, 10), (10, 0)]);
+        assert_eq!(
+            render_distribution(&counts, 4),
+            "bucket  0 | ##   5\nbucket  1 | #### 10\nbucket 10 |      0\n"
+        );
+        assert_eq!(render_distribution(&HashMap::new(), 4), "");
+    }
+
+    #[tokio::test]
+    async fn test_weighted_routing_end_to_end() {
+        let mut service = service(3);
+        service.set_weights(vec![2, 1, 5]);
+        let cid: Arc<str> = Arc::from("client123");
+        service.add_client_sync(cid.clone()).await.unwrap();
+        service.set_is_active_sync(cid.clone(), true).await.unwrap();
+        assert_eq!(service.get_is_active(cid).await, Ok(true));
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "need one weight per bucket")]
+    async fn test_set_weights_rejects_wrong_length() {
+        service(2).set_weights(vec![1]);
+    }
+
+    #[tokio::test]
+    async fn test_default_hasher_is_stable_across_services() {
+        let (first, second) = (service(8), service(8));
+        for id in client_ids(1000) {
+            assert_eq!(first.get_bucket(&id), second.get_bucket(&id));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_bucket_remaps_fraction_of_keys() {
+        let ids = client_ids(10_000);
+        let mut service = service(1);
+
+        for n in 1..8 {
+            let before: Vec<usize> = ids.iter().map(|id| service.get_bucket(id)).collect();
+            service.add_bucket();
+            assert_eq!(service.bucket_count(), n + 1);
+
+            let moved = ids
+                .iter()
+                .zip(&before)
+                .filter(|(id, b)| service.get_bucket(id) != **b)
+                .count();
+            // ~1/(n+1) of the ids move, all of them onto the new bucket
+            let expected = ids.len() as f64 / (n + 1) as f64;
+            assert!((moved as f64 - expected).abs() < expected * 0.2, "n={n} moved={moved}");
+            assert!(ids.iter().zip(&before).all(|(id, b)| {
+                let after = service.get_bucket(id);
+                after == *b || after == n
+            }));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_remove_bucket_only_remaps_its_keys() {
+        let ids = client_ids(10_000);
+        let mut service = service(4);
+        let before: Vec<usize> = ids.iter().map(|id| service.get_bucket(id)).collect();
+
+        assert_eq!(service.remove_bucket().await, Ok(true));
+        assert_eq!(service.bucket_count(), 3);
+        for (id, b) in ids.iter().zip(&before) {
+            if *b != 3 {
+                assert_eq!(service.get_bucket(id), *b);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_remove_bucket_moves_its_clients() {
+        let mut service = service(3);
+        let ids: Vec<Arc<str>> = client_ids(100).into_iter().map(Arc::from).collect();
+        for (i, id) in ids.iter().enumerate() {
+            service.add_client_sync(id.clone()).await.unwrap();
+            service.set_is_active_sync(id.clone(), i.is_multiple_of(2)).await.unwrap();
+        }
+        let removed = ids.iter().filter(|id| service.get_bucket(id) == 2).count();
+        assert!(removed > 0);
+
+        assert_eq!(service.remove_bucket().await, Ok(true));
+        assert_eq!(service.total_client_count().await, Ok(100));
+        for (i, id) in ids.iter().enumerate() {
+            assert_eq!(service.get_is_active(id.clone()).await, Ok(i.is_multiple_of(2)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_buckets_do_not_block_each_other() {
+        let (stalled_tx, mut stalled_rx) = mpsc::channel::<Commands>(CHANNEL_CAPACITY);
+        // bucket 0 picks up a command and never finishes handling it
+        tokio::spawn(async move {
+            let _command = stalled_rx.recv().await;
+            std::future::pending::<()>().await;
+        });
+        let (tx, rx) = mpsc::channel::<Commands>(CHANNEL_CAPACITY);
+        let live = tokio::spawn(event_loop(Gateway::new(None, broadcast::channel(1).0), rx, None));
+        let service = raw_service(vec![stalled_tx, tx], vec![live]);
+
+        let ids = client_ids(100);
+        let stalled: Arc<str> = Arc::from(ids.iter().find(|id| service.get_bucket(id) == 0).unwrap().as_str());
+        let cid: Arc<str> = Arc::from(ids.iter().find(|id| service.get_bucket(id) == 1).unwrap().as_str());
+
+        service.add_client(stalled).await.unwrap();
+        let served = tokio::time::timeout(Duration::from_secs(1), async {
+            service.add_client(cid.clone()).await.unwrap();
+            service.set_is_active(cid.clone(), true).await.unwrap();
+            service.get_is_active(cid.clone()).await.unwrap()
+        })
+        .await;
+        assert_eq!(served, Ok(true));
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_set_is_active() {
+        let service = service(4);
+        let ids: Vec<Arc<str>> = client_ids(50).into_iter().map(Arc::from).collect();
+        for id in &ids {
+            service.add_client(id.clone()).await.unwrap();
+        }
+        // the ids should be spread over more than one bucket
+        let mut buckets: Vec<usize> = ids.iter().map(|id| service.get_bucket(id)).collect();
+        buckets.sort();
+        buckets.dedup();
+        assert!(buckets.len() > 1);
+
+        service.broadcast_set_is_active(true).await.unwrap();
+        for id in &ids {
+            assert!(service.get_is_active(id.clone()).await.unwrap());
+        }
+
+        service.broadcast_set_is_active(false).await.unwrap();
+        for id in &ids {
+            assert!(!service.get_is_active(id.clone()).await.unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_total_client_count() {
+        let service = service(4);
+        assert_eq!(service.total_client_count().await.unwrap(), 0);
+
+        for id in client_ids(25) {
+            service.add_client(Arc::from(id)).await.unwrap();
+        }
+        assert_eq!(service.total_client_count().await.unwrap(), 25);
+
+        service.remove_client(Arc::from("client0")).await.unwrap();
+        assert_eq!(service.total_client_count().await.unwrap(), 24);
+    }
+
+    #[tokio::test]
+    async fn test_total_client_count_bucket_reply_fails() {
+        let (tx, mut rx) = mpsc::channel::<Commands>(1);
+        tokio::spawn(async move { while rx.recv().await.is_some() {} });
+        let mut service = service(1);
+        service.clients.push(tx);
+
+        assert_eq!(service.total_client_count().await, Err(GatewayError::RecvFailed));
+    }
+
+    #[tokio::test]
+    async fn test_list_active_clients() {
+        let service = service(4);
+        let ids = client_ids(20);
+        for id in &ids {
+            service.add_client(Arc::from(id.as_str())).await.unwrap();
+        }
+        let mut expected: Vec<String> = ids.iter().step_by(3).cloned().collect();
+        for id in &expected {
+            service.set_is_active(Arc::from(id.as_str()), true).await.unwrap();
+        }
+
+        let mut active = service.list_active_clients().await.unwrap();
+        active.sort();
+        expected.sort();
+        assert_eq!(active, expected);
+    }
+
+    #[test]
+    fn test_client_builder() {
+        let client: Client<u64> = Client::builder().build();
+        assert_eq!(client.state, ClientState::Connecting);
+        assert_eq!((client.value, client.tokens), (0, 0.0));
+        assert!(client.metadata.is_empty());
+
+        let metadata = HashMap::from([(String::from("region"), String::from("eu"))]);
+        let client = Client::builder().state(ClientState::Active).value(7u64).metadata(metadata.clone()).tokens(3.0).build();
+        assert!(client.is_active());
+        assert_eq!((client.value, client.tokens), (7, 3.0));
+        assert_eq!(client.metadata, metadata);
+        assert_eq!(client.last_refill, client.last_seen);
+    }
+
+    #[test]
+    fn test_gateway_sweep_expired() {
+        let mut gateway: Gateway = Gateway::new(None, broadcast::channel(1).0);
+        gateway.add_client(Arc::from("stale"));
+        gateway.add_client(Arc::from("fresh"));
+        gateway.clients.get_mut("stale").unwrap().last_seen = Instant::now() - Duration::from_secs(60);
+
+        assert_eq!(gateway.sweep_expired(Duration::from_secs(30)), vec![String::from("stale")]);
+        assert_eq!(gateway.client_count(), 1);
+        assert!(gateway.clients.contains_key("fresh"));
+    }
+
+    #[tokio::test]
+    async fn test_sweep_expired_command() {
+        let service = service(2);
+        service.add_client(Arc::from("stale")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        service.add_client(Arc::from("fresh")).await.unwrap();
+
+        let expired = service.sweep_expired(Duration::from_millis(30)).await.unwrap();
+        assert_eq!(expired, vec![String::from("stale")]);
+        assert_eq!(service.total_client_count().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_keeps_client_alive() {
+        let service = service(2);
+        let cid: Arc<str> = Arc::from("client123");
+        service.add_client(cid.clone()).await.unwrap();
+        service.add_client(Arc::from("silent")).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        service.heartbeat(cid.clone()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let expired = service.sweep_expired(Duration::from_millis(50)).await.unwrap();
+        assert_eq!(expired, vec![String::from("silent")]);
+        assert!(service.get_is_active(cid).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_periodic_sweep() {
+        let service = GatewayService::builder()
+            .sweep(SweepConfig { every: Duration::from_millis(10), ttl: Duration::from_millis(30) })
+            .build();
+        service.add_client(Arc::from("client123")).await.unwrap();
+        assert_eq!(service.total_client_count().await.unwrap(), 1);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(service.total_client_count().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_fire_and_forget_reach_the_routed_bucket() {
+        let (senders, mut receivers): (Vec<_>, Vec<_>) = (0..3).map(|_| mpsc::channel::<Commands>(CHANNEL_CAPACITY)).unzip();
+        let service = raw_service(senders, vec![]);
+        for id in client_ids(30) {
+            let cid: Arc<str> = Arc::from(id);
+            let bucket = service.get_bucket(&cid);
+            service.add_client(cid.clone()).await.unwrap();
+            service.set_is_active(cid.clone(), true).await.unwrap();
+            assert!(matches!(
+                receivers[bucket].try_recv(),
+                Ok(Commands::AddClient { client_id, ack: None }) if client_id == cid
+            ));
+            assert!(matches!(
+                receivers[bucket].try_recv(),
+                Ok(Commands::SetState { client_id, state: ClientState::Active, ack: None }) if client_id == cid
+            ));
+            assert!(receivers.iter_mut().all(|rx| rx.try_recv().is_err()));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_same_client_commands_apply_in_order() {
+        let service = Arc::new(service(4));
+        let writers: Vec<_> = client_ids(16)
+            .into_iter()
+            .enumerate()
+            .map(|(writer, id)| {
+                let service = service.clone();
+                tokio::spawn(async move {
+                    let cid: Arc<str> = Arc::from(id);
+                    service.add_client(cid.clone()).await.unwrap();
+                    // an odd number of flips for even writers, even for odd ones
+                    let flips = 1_000 + writer % 2;
+                    for i in 0..flips {
+                        service.set_is_active(cid.clone(), i % 2 == 0).await.unwrap();
+                    }
+                    (cid, (flips - 1) % 2 == 0)
+                })
+            })
+            .collect();
+        for writer in writers {
+            let (cid, last) = writer.await.unwrap();
+            assert_eq!(service.get_is_active(cid).await, Ok(last));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_commands_are_applied_on_return() {
+        let (tx, mut rx) = mpsc::channel::<Commands>(CHANNEL_CAPACITY);
+        let service = raw_service(vec![tx], vec![]);
+        let cid: Arc<str> = Arc::from("client123");
+        let mut gateway: Gateway = Gateway::new(None, broadcast::channel(1).0);
+
+        // drive the bucket by hand so nothing is applied behind our back
+        let (added, _) = tokio::join!(service.add_client_sync(cid.clone()), async {
+            match rx.recv().await {
+                Some(Commands::AddClient { client_id, ack }) => {
+                    gateway.add_client(client_id);
+                    ack.unwrap().send(Ok(true)).unwrap();
+                }
+                _ => panic!("expected AddClient"),
+            }
+        });
+        added.unwrap();
+        assert_eq!(gateway.client_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sync_set_then_get_without_sleep() {
+        let service = service(4);
+        for id in client_ids(50) {
+            let cid: Arc<str> = Arc::from(id);
+            service.add_client_sync(cid.clone()).await.unwrap();
+            for is_active in [true, false, true] {
+                service.set_is_active_sync(cid.clone(), is_active).await.unwrap();
+                assert_eq!(service.get_is_active(cid.clone()).await, Ok(is_active));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_commands_fail_without_ack() {
+        let (tx, mut rx) = mpsc::channel::<Commands>(1);
+        tokio::spawn(async move { while rx.recv().await.is_some() {} });
+        let service = raw_service(vec![tx], vec![]);
+        let cid: Arc<str> = Arc::from("client123");
+
+        assert_eq!(service.add_client_sync(cid.clone()).await, Err(GatewayError::RecvFailed));
+        assert_eq!(service.set_is_active_sync(cid, true).await, Err(GatewayError::RecvFailed));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let service = service(3);
+        let cid: Arc<str> = Arc::from("client123");
+        service.add_client(cid.clone()).await.unwrap();
+        let probes: Vec<Sender<Commands>> = service.clients.clone();
+
+        tokio::time::timeout(Duration::from_secs(1), service.shutdown()).await.unwrap();
+        // every event loop has exited and dropped its receiver
+        assert!(probes.iter().all(|probe| probe.is_closed()));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_timeout_aborts_stuck_buckets() {
+        let (tx, rx) = mpsc::channel::<Commands>(CHANNEL_CAPACITY);
+        let healthy = tokio::spawn(event_loop(Gateway::new(None, broadcast::channel(1).0), rx, None));
+        // a bucket stuck in a slow command handler that never polls its queue
+        let (stuck_tx, _stuck_rx) = mpsc::channel::<Commands>(1);
+        let (alive, dropped) = oneshot::channel::<()>();
+        let stuck = tokio::spawn(async move {
+            let _alive = alive;
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+        });
+        let service = raw_service(vec![tx.clone(), stuck_tx], vec![healthy, stuck]);
+
+        let start = Instant::now();
+        assert_eq!(service.shutdown_timeout(Duration::from_millis(50)).await, vec![1]);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        // the healthy loop exited on its own and the stuck task was dropped
+        assert!(tx.is_closed());
+        assert!(dropped.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_methods_take_str_and_string() {
+        let service = service(4);
+        assert_eq!(service.add_client_sync("client123").await, Ok(true));
+        service.set_is_active_sync(String::from("client123"), true).await.unwrap();
+        assert_eq!(service.get_is_active("client123").await, Ok(true));
+        assert_eq!(service.get_is_active(Arc::<str>::from("client123")).await, Ok(true));
+
+        service.add_clients(["client1", "client2"]).await.unwrap();
+        service.add_clients(vec![String::from("client3")]).await.unwrap();
+        assert_eq!(service.total_client_count().await, Ok(4));
+        assert_eq!(service.remove_client(String::from("client3")).await, Ok(true));
+    }
+
+    /// Polls the read snapshot until it shows `expected` for `client_id`.
+    async fn snapshot_shows(service: &GatewayService, client_id: &str, expected: Option<bool>) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while service.get_is_active_snapshot(client_id) != expected {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("snapshot never caught up");
+    }
+
+    #[tokio::test]
+    async fn test_read_snapshot_eventually_observes_writes() {
+        let service = GatewayService::builder().buckets(4).read_snapshots().build();
+        assert_eq!(service.get_is_active_snapshot("client123"), None);
+
+        service.add_client("client123").await.unwrap();
+        snapshot_shows(&service, "client123", Some(false)).await;
+        service.set_is_active("client123", true).await.unwrap();
+        snapshot_shows(&service, "client123", Some(true)).await;
+        service.remove_client("client123").await.unwrap();
+        snapshot_shows(&service, "client123", None).await;
+
+        // a burst of writes is published as a whole
+        let ids = client_ids(200);
+        for id in &ids {
+            service.add_client(id.as_str()).await.unwrap();
+        }
+        service.broadcast_set_is_active(true).await.unwrap();
+        for id in &ids {
+            snapshot_shows(&service, id, Some(true)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_snapshot_off_by_default() {
+        let service = service(2);
+        service.add_client_sync("client123").await.unwrap();
+        service.wait_idle().await.unwrap();
+        assert_eq!(service.get_is_active_snapshot("client123"), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_snapshot_follows_bucket_changes() {
+        let mut service = GatewayService::builder().buckets(2).read_snapshots().build();
+        service.add_bucket();
+        assert_eq!(service.snapshots.len(), 3);
+
+        service.handles[1].abort();
+        while !service.handles[1].is_finished() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(service.restart_dead_buckets().await, vec![1]);
+        let id = client_ids(100).into_iter().find(|id| service.get_bucket(id) == 1).unwrap();
+        service.add_client(id.as_str()).await.unwrap();
+        snapshot_shows(&service, &id, Some(false)).await;
+
+        assert_eq!(service.remove_bucket().await, Ok(true));
+        assert_eq!(service.snapshots.len(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_compare_and_set_race_has_one_winner() {
+        let service = Arc::new(service(2));
+        for round in 0..50 {
+            let id = format!("client{round}");
+            service.add_client_sync(id.as_str()).await.unwrap();
+            let racers: Vec<_> = (0..2)
+                .map(|_| {
+                    let (service, id) = (service.clone(), id.clone());
+                    tokio::spawn(async move { service.compare_and_set(id, false, true).await.unwrap() })
+                })
+                .collect();
+            let mut wins = 0;
+            for racer in racers {
+                wins += racer.await.unwrap() as usize;
+            }
+            assert_eq!(wins, 1, "round {round}");
+            assert_eq!(service.get_is_active(id).await, Ok(true));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compare_and_set_mismatch_and_unknown() {
+        let service = service(2);
+        let mut events = service.subscribe();
+        assert_eq!(service.compare_and_set("client123", false, true).await, Ok(false));
+
+        service.add_client_sync("client123").await.unwrap();
+        assert_eq!(service.compare_and_set("client123", true, false).await, Ok(false));
+        assert_eq!(service.compare_and_set("client123", false, true).await, Ok(true));
+        assert_eq!(service.get_is_active("client123").await, Ok(true));
+        assert!(events.recv().await.unwrap().is_active);
+    }
+
+    #[tokio::test]
+    async fn test_client_state_transitions() {
+        let service = service(2);
+        let mut events = service.subscribe();
+        assert_eq!(service.get_state("client123").await, Ok(None));
+
+        service.add_client_sync("client123").await.unwrap();
+        assert_eq!(service.get_state("client123").await, Ok(Some(ClientState::Connecting)));
+        for (state, is_active) in [
+            (ClientState::Active, true),
+            (ClientState::Draining, false),
+            (ClientState::Disconnected, false),
+            (ClientState::Active, true),
+        ] {
+            service.set_state_sync("client123", state).await.unwrap();
+            assert_eq!(service.get_state("client123").await, Ok(Some(state)));
+            assert_eq!(state.is_active(), is_active);
+            assert_eq!(service.get_is_active("client123").await, Ok(is_active), "{state:?}");
+        }
+
+        // only flips of is_active are announced, not Draining -> Disconnected
+        let flips: Vec<bool> = std::iter::from_fn(|| events.try_recv().ok()).map(|change| change.is_active).collect();
+        assert_eq!(flips, [true, false, true]);
+
+        service.set_is_active_sync("client123", false).await.unwrap();
+        assert_eq!(service.get_state("client123").await, Ok(Some(ClientState::Disconnected)));
+    }
+
+    #[tokio::test]
+    async fn test_wait_idle_after_fire_and_forget() {
+        let service = service(4);
+        let ids = client_ids(500);
+        for id in &ids {
+            service.add_client(id.as_str()).await.unwrap();
+            service.set_is_active(id.as_str(), true).await.unwrap();
+        }
+        service.wait_idle().await.unwrap();
+        // nothing left queued, without reading through the queues themselves
+        assert!(service.clients.iter().all(|sender| sender.capacity() == sender.max_capacity()));
+
+        let snapshot = service.snapshot().await.unwrap();
+        assert_eq!(snapshot.len(), 500);
+        assert!(snapshot.iter().all(|(_, is_active)| *is_active));
+    }
+
+    #[tokio::test]
+    async fn test_ping_all_reports_dead_bucket() {
+        let mut service = service(3);
+        assert_eq!(service.ping_all().await, vec![true, true, true]);
+
+        service.handles[1].abort();
+        assert!((&mut service.handles[1]).await.unwrap_err().is_cancelled());
+        assert_eq!(service.ping_all().await, vec![true, false, true]);
+    }
+
+    #[tokio::test]
+    async fn test_ping_all_times_out_stuck_bucket() {
+        let (tx, rx) = mpsc::channel::<Commands>(CHANNEL_CAPACITY);
+        let healthy = tokio::spawn(event_loop(Gateway::new(None, broadcast::channel(1).0), rx, None));
+        // a receiver nothing reads from, like a loop stuck in a handler
+        let (stuck_tx, _stuck_rx) = mpsc::channel::<Commands>(1);
+        let service = raw_service(vec![stuck_tx, tx], vec![healthy]);
+
+        let start = Instant::now();
+        assert_eq!(service.ping_all().await, vec![false, true]);
+        assert!(start.elapsed() < PING_TIMEOUT * 2);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_timeout_graceful() {
+        let service = service(3);
+        service.add_client_sync(Arc::from("client123")).await.unwrap();
+        assert!(service.shutdown_timeout(Duration::from_secs(1)).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_drain_finishes_queued_and_rejects_new() {
+        let service = service(1);
+        let ids: Vec<Arc<str>> = client_ids(100).into_iter().map(Arc::from).collect();
+        // fire-and-forget, so these are still queued when the drain is sent
+        for id in &ids {
+            service.add_client(id.clone()).await.unwrap();
+            service.set_is_active(id.clone(), true).await.unwrap();
+        }
+        service.set_is_active(ids[1].clone(), false).await.unwrap();
+        service.drain(0).await.unwrap();
+        assert_eq!(service.total_client_count().await, Ok(100));
+        assert_eq!(service.list_active_clients().await.unwrap().len(), 99);
+
+        let cid: Arc<str> = Arc::from("late");
+        assert_eq!(service.add_client_sync(cid.clone()).await, Err(GatewayError::Draining));
+        assert_eq!(service.set_is_active_sync(ids[0].clone(), false).await, Err(GatewayError::Draining));
+        service.add_client(cid.clone()).await.unwrap();
+        assert_eq!(service.get_or_create(cid.clone()).await, Err(GatewayError::Draining));
+        assert_eq!(service.add_clients([cid.clone()]).await, Err(GatewayError::Draining));
+        let record = ClientRecord { client_id: cid.to_string(), is_active: true, value: (), metadata: HashMap::new() };
+        assert_eq!(service.import(vec![vec![record]], true).await, Err(GatewayError::Draining));
+        assert_eq!(service.broadcast_set_is_active(true).await, Err(GatewayError::Draining));
+        // a heartbeat cannot reactivate a client
+        service.heartbeat(ids[1].clone()).await.unwrap();
+        assert_eq!(service.get_is_active(ids[1].clone()).await, Ok(false));
+        assert_eq!(service.total_client_count().await, Ok(100));
+        // reads still work
+        assert_eq!(service.get_is_active(ids[0].clone()).await, Ok(true));
+    }
+
+    #[tokio::test]
+    async fn test_drain_only_affects_its_bucket() {
+        let service = service(2);
+        service.drain(1).await.unwrap();
+        let ids: Vec<Arc<str>> = client_ids(50).into_iter().map(Arc::from).collect();
+        for id in &ids {
+            let expected = if service.get_bucket(id) == 0 { Ok(true) } else { Err(GatewayError::Draining) };
+            assert_eq!(service.add_client_sync(id.clone()).await, expected);
+        }
+        // the bucket that is not draining still applies a broadcast
+        assert_eq!(service.broadcast_set_is_active(true).await, Err(GatewayError::Draining));
+        assert_eq!(service.list_active_clients().await.unwrap().len(), service.total_client_count().await.unwrap());
+        assert_eq!(service.drain(2).await, Err(GatewayError::BucketNotFound(2)));
+    }
+
+    #[tokio::test]
+    async fn test_remove_bucket_drains_queue() {
+        let mut service = service(1);
+        let cid: Arc<str> = Arc::from("client123");
+        service.add_client(cid.clone()).await.unwrap();
+
+        assert_eq!(service.remove_bucket().await, Ok(true));
+        assert_eq!(service.remove_bucket().await, Ok(false));
+        assert_eq!(service.get_is_active(cid).await, Err(GatewayError::BucketNotFound(0)));
+    }
+
+    #[tokio::test]
+    async fn test_no_buckets_fails_without_panicking() {
+        let service = service(0);
+        let cid: Arc<str> = Arc::from("client123");
+        assert_eq!(service.get_bucket(&cid), 0);
+        assert!(service.get_buckets(&cid, 3).is_empty());
+        assert_eq!(service.add_client(cid.clone()).await, Err(GatewayError::BucketNotFound(0)));
+        assert_eq!(service.set_is_active(cid.clone(), true).await, Err(GatewayError::BucketNotFound(0)));
+        assert_eq!(service.add_client_sync(cid.clone()).await, Err(GatewayError::BucketNotFound(0)));
+        assert_eq!(service.try_add_client(cid.clone()), Err(GatewayError::BucketNotFound(0)));
+        assert_eq!(service.get_is_active(cid.clone()).await, Err(GatewayError::BucketNotFound(0)));
+        assert_eq!(service.add_clients([cid.clone()]).await, Err(GatewayError::BucketNotFound(0)));
+        assert_eq!(service.total_client_count().await, Ok(0));
+
+        let path = TempPath::new("no-buckets.bin");
+        let saved = GatewayService::new(1);
+        saved.add_client_sync(cid).await.unwrap();
+        saved.save(&path.0).await.unwrap();
+        let err = service.load(&path.0).await.unwrap_err();
+        assert_eq!(err.get_ref().and_then(|e| e.downcast_ref::<GatewayError>()), Some(&GatewayError::BucketNotFound(0)));
+    }
+
+    #[tokio::test]
+    async fn test_out_of_range_bucket_is_an_error() {
+        // routing still spreads ids over two weighted buckets, but neither
+        // has a sender any more
+        let mut service = raw_service(vec![], vec![]);
+        service.weights = vec![1, 1];
+        for id in client_ids(20) {
+            let cid: Arc<str> = Arc::from(id);
+            let bucket = service.get_bucket(&cid);
+            assert_eq!(service.add_client(cid.clone()).await, Err(GatewayError::BucketNotFound(bucket)));
+            assert_eq!(service.set_is_active(cid, true).await, Err(GatewayError::BucketNotFound(bucket)));
+        }
+    }
+
+    fn limited_service(rate_limit: RateLimit) -> GatewayService {
+        GatewayService::builder().buckets(2).rate_limit(rate_limit).build()
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_rejects_over_budget() {
+        let service = limited_service(RateLimit { per_second: 1.0, burst: 3 });
+        let cid: Arc<str> = Arc::from("client123");
+        service.add_client_sync(cid.clone()).await.unwrap();
+
+        let mut results = vec![];
+        for i in 0..10 {
+            results.push(service.set_is_active_sync(cid.clone(), i % 2 == 0).await);
+        }
+        assert_eq!(&results[..3], &[Ok(()), Ok(()), Ok(())]);
+        assert!(results[3..].iter().all(|r| *r == Err(GatewayError::RateLimited)));
+        assert_eq!(service.set_meta(cid.clone(), "region", "eu").await, Err(GatewayError::RateLimited));
+        // rejected writes were not applied
+        assert_eq!(service.get_is_active(cid).await, Ok(true));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_applies_to_compare_and_set() {
+        let service = limited_service(RateLimit { per_second: 1.0, burst: 2 });
+        service.add_client_sync("client123").await.unwrap();
+
+        assert_eq!(service.compare_and_set("client123", false, true).await, Ok(true));
+        assert_eq!(service.compare_and_set("client123", false, true).await, Ok(false));
+        assert_eq!(service.compare_and_set("client123", true, false).await, Err(GatewayError::RateLimited));
+        // the throttled swap was not applied
+        assert_eq!(service.get_is_active("client123").await, Ok(true));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_is_per_client_and_refills() {
+        let service = limited_service(RateLimit { per_second: 50.0, burst: 1 });
+        let (a, b): (Arc<str>, Arc<str>) = (Arc::from("a"), Arc::from("b"));
+        service.add_client_sync(a.clone()).await.unwrap();
+        service.add_client_sync(b.clone()).await.unwrap();
+
+        assert_eq!(service.set_is_active_sync(a.clone(), true).await, Ok(()));
+        assert_eq!(service.set_is_active_sync(a.clone(), true).await, Err(GatewayError::RateLimited));
+        assert_eq!(service.set_is_active_sync(b.clone(), true).await, Ok(()));
+
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert_eq!(service.set_is_active_sync(a, false).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_commands_do_not_spawn_tasks() {
+        let service = service(2);
+        let metrics = tokio::runtime::Handle::current().metrics();
+        let before = metrics.num_alive_tasks();
+
+        for id in client_ids(1000) {
+            service.add_client(Arc::from(id)).await.unwrap();
+        }
+        // a queued or in-flight command is not a task of its own
+        assert_eq!(metrics.num_alive_tasks(), before);
+        assert_eq!(service.total_client_count().await, Ok(1000));
+        assert_eq!(metrics.num_alive_tasks(), before);
+    }
+
+    #[tokio::test]
+    async fn test_add_clients_batch() {
+        let service = service(4);
+        let ids: Vec<Arc<str>> = client_ids(1000).into_iter().map(Arc::from).collect();
+        service.add_clients(ids.clone()).await.unwrap();
+
+        assert_eq!(service.total_client_count().await, Ok(1000));
+        // each id landed in the bucket single-client commands route to
+        service.set_is_active_sync(ids[42].clone(), true).await.unwrap();
+        assert_eq!(service.get_is_active(ids[42].clone()).await, Ok(true));
+    }
+
+    #[tokio::test]
+    async fn test_add_clients_sends_one_command_per_bucket() {
+        let (tx, mut rx) = mpsc::channel::<Commands>(CHANNEL_CAPACITY);
+        let service = raw_service(vec![tx], vec![]);
+        let ids: Vec<Arc<str>> = client_ids(100).into_iter().map(Arc::from).collect();
+
+        let (added, _) = tokio::join!(service.add_clients(ids), async {
+            match rx.recv().await {
+                Some(Commands::AddClients { client_ids, sender }) => {
+                    assert_eq!(client_ids.len(), 100);
+                    sender.send(Ok(())).unwrap();
+                }
+                _ => panic!("expected AddClients"),
+            }
+        });
+        added.unwrap();
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_receives_flips() {
+        let service = service(4);
+        let mut events = service.subscribe();
+        let cid: Arc<str> = Arc::from("client123");
+        service.add_client_sync(cid.clone()).await.unwrap();
+
+        service.set_is_active_sync(cid.clone(), true).await.unwrap();
+        // setting the same value again is not a flip
+        service.set_is_active_sync(cid.clone(), true).await.unwrap();
+        service.set_is_active_sync(cid.clone(), false).await.unwrap();
+
+        assert_eq!(events.recv().await, Ok(StateChange { client_id: cid.clone(), is_active: true }));
+        assert_eq!(events.recv().await, Ok(StateChange { client_id: cid, is_active: false }));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_lagging_subscriber_recovers() {
+        let service = service(1);
+        let mut events = service.subscribe();
+        let cid: Arc<str> = Arc::from("client123");
+        service.add_client_sync(cid.clone()).await.unwrap();
+
+        for i in 0..EVENT_CAPACITY + 10 {
+            service.set_is_active_sync(cid.clone(), i % 2 == 0).await.unwrap();
+        }
+        assert_eq!(events.recv().await, Err(broadcast::error::RecvError::Lagged(10)));
+        assert!(events.recv().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_stats_count_commands() {
+        let service = service(2);
+        let before = service.stats().await.unwrap();
+        assert_eq!(before.len(), 2);
+        assert!(before.iter().all(|stats| stats.commands_received == 0 && stats.client_count == 0));
+        assert!(before.iter().all(|stats| stats.max_capacity == CHANNEL_CAPACITY));
+
+        for id in client_ids(20) {
+            let cid: Arc<str> = Arc::from(id);
+            service.add_client_sync(cid.clone()).await.unwrap();
+            service.get_is_active(cid).await.unwrap();
+        }
+        let after = service.stats().await.unwrap();
+        let total = |name: &str| after.iter().map(|stats| stats.commands_by_type.get(name).copied().unwrap_or(0)).sum::<u64>();
+        assert_eq!(total("AddClient"), 20);
+        assert_eq!(total("GetIsActive"), 20);
+        // the first Stats request, but not the one being answered
+        assert_eq!(total("Stats"), 2);
+        assert_eq!(after.iter().map(|stats| stats.commands_received).sum::<u64>(), 42);
+        assert_eq!(after.iter().map(|stats| stats.client_count).sum::<usize>(), 20);
+        assert!(after.iter().all(|stats| stats.queue_capacity == stats.max_capacity));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_text() {
+        let service = service(2);
+        let mut per_bucket = [0; 2];
+        for id in client_ids(20) {
+            per_bucket[service.get_bucket(&id)] += 1;
+            service.add_client_sync(Arc::from(id)).await.unwrap();
+        }
+        let text = service.metrics_text().await.unwrap();
+        assert!(text.contains("# HELP gateway_clients_total Clients held by the bucket.\n"));
+        assert!(text.contains("# TYPE gateway_clients_total gauge\n"));
+        for (bucket, clients) in per_bucket.into_iter().enumerate() {
+            assert!(text.contains(&format!("gateway_clients_total{{bucket=\"{bucket}\"}} {clients}\n")));
+            assert!(text.contains(&format!("gateway_commands_by_type_total{{bucket=\"{bucket}\",command=\"AddClient\"}} {clients}\n")));
+        }
+        assert!(text.contains("# TYPE gateway_commands_total counter\n"));
+        assert!(text.contains("gateway_queue_depth{bucket=\"1\"} 0\n"));
+        // every line is a comment or a `name{labels} value` sample
+        for line in text.lines().filter(|line| !line.starts_with('#')) {
+            let (series, value) = line.rsplit_once(' ').unwrap();
+            assert!(series.starts_with("gateway_") && series.ends_with('}'), "{line}");
+            value.parse::<u64>().unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_new_service_end_to_end() {
+        let service = GatewayService::new(4);
+        assert_eq!(service.bucket_count(), 4);
+
+        let ids: Vec<Arc<str>> = client_ids(100).into_iter().map(Arc::from).collect();
+        for id in &ids {
+            service.add_client_sync(id.clone()).await.unwrap();
+        }
+        for id in ids.iter().step_by(2) {
+            service.set_is_active_sync(id.clone(), true).await.unwrap();
+        }
+        assert_eq!(service.get_is_active(ids[0].clone()).await, Ok(true));
+        assert_eq!(service.get_is_active(ids[1].clone()).await, Ok(false));
+        assert_eq!(service.list_active_clients().await.unwrap().len(), 50);
+        assert_eq!(service.remove_client(ids[0].clone()).await, Ok(true));
+        assert_eq!(service.total_client_count().await, Ok(99));
+        // every bucket got some of the load
+        assert!(service.stats().await.unwrap().iter().all(|stats| stats.client_count > 0));
+        service.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_buckets_distinct() {
+        let service = service(5);
+        for id in client_ids(200) {
+            let buckets = service.get_buckets(&id, 3);
+            assert_eq!(buckets.len(), 3);
+            assert_eq!(buckets[0], service.get_bucket(&id));
+            let mut distinct = buckets.clone();
+            distinct.sort();
+            distinct.dedup();
+            assert_eq!(distinct.len(), 3);
+        }
+        // asking for more replicas than buckets yields each bucket once
+        assert_eq!(service.get_buckets("client1", 9).len(), 5);
+        assert!(raw_service(vec![], vec![]).get_buckets("client1", 2).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_replicated_read_survives_primary_loss() {
+        let mut service = service(4);
+        let cid: Arc<str> = Arc::from("client123");
+        service.add_client_replicated(cid.clone(), 2).await.unwrap();
+        service.set_is_active_replicated(cid.clone(), true, 2).await.unwrap();
+        assert_eq!(service.total_client_count().await, Ok(2));
+
+        let primary = service.get_bucket(&cid);
+        service.handles[primary].abort();
+        assert!((&mut service.handles[primary]).await.unwrap_err().is_cancelled());
+
+        assert_eq!(service.get_is_active(cid.clone()).await, Err(GatewayError::SendFailed));
+        assert_eq!(service.get_is_active_replicated(cid.clone(), 2).await, Ok(true));
+        assert_eq!(service.get_is_active_replicated(cid, 1).await, Err(GatewayError::SendFailed));
+    }
+
+    #[test]
+    fn test_gateway_error_display() {
+        let cases = [
+            (GatewayError::BucketNotFound(3), "bucket 3 not found"),
+            (GatewayError::SendFailed, "failed to send command: bucket is closed"),
+            (GatewayError::RecvFailed, "bucket dropped the command without replying"),
+            (GatewayError::Full, "bucket queue is full"),
+            (GatewayError::RateLimited, "client is rate limited"),
+            (GatewayError::Timeout, "timed out waiting for bucket"),
+            (GatewayError::Draining, "bucket is draining"),
+        ];
+        for (error, expected) in cases {
+            assert_eq!(error.to_string(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gateway_error_propagates_as_box_dyn_error() {
+        async fn lookup(service: &GatewayService) -> Result<bool, Box<dyn std::error::Error>> {
+            Ok(service.get_is_active(Arc::from("client123")).await?)
+        }
+
+        let (tx, rx) = mpsc::channel::<Commands>(1);
+        drop(rx);
+        let err = lookup(&raw_service(vec![tx], vec![])).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<GatewayError>(), Some(&GatewayError::SendFailed));
+    }
+
+    #[tokio::test]
+    async fn test_get_is_active_times_out_on_stalled_bucket() {
+        // a bucket that accepts commands but never answers them
+        let (tx, _rx) = mpsc::channel::<Commands>(CHANNEL_CAPACITY);
+        let mut service = raw_service(vec![tx], vec![]);
+        service.set_request_timeout(Some(Duration::from_millis(20)));
+
+        let started = Instant::now();
+        assert_eq!(service.get_is_active(Arc::from("client123")).await, Err(GatewayError::Timeout));
+        assert!(started.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_leaves_live_buckets_alone() {
+        let service = GatewayService::builder().buckets(2).request_timeout(Duration::from_secs(5)).build();
+        let cid: Arc<str> = Arc::from("client123");
+        service.add_client_sync(cid.clone()).await.unwrap();
+        service.set_is_active_sync(cid.clone(), true).await.unwrap();
+        assert_eq!(service.get_is_active(cid).await, Ok(true));
+    }
+
+    #[tokio::test]
+    async fn test_restart_dead_buckets() {
+        let mut service = service(2);
+        // swap bucket 1 for one whose event loop panics straight away
+        let (tx, rx) = mpsc::channel::<Commands>(CHANNEL_CAPACITY);
+        service.clients[1] = tx;
+        service.handles[1] = tokio::spawn(async move {
+            let _rx = rx;
+            panic!("bucket crashed");
+        });
+        while !service.handles[1].is_finished() {
+            tokio::task::yield_now().await;
+        }
+
+        let cid: Arc<str> = client_ids(100).into_iter().map(Arc::from).find(|id: &Arc<str>| service.get_bucket(id) == 1).unwrap();
+        assert_eq!(service.add_client_sync(cid.clone()).await, Err(GatewayError::SendFailed));
+
+        assert_eq!(service.restart_dead_buckets().await, vec![1]);
+        assert!(service.restart_dead_buckets().await.is_empty());
+        service.add_client_sync(cid.clone()).await.unwrap();
+        service.set_is_active_sync(cid.clone(), true).await.unwrap();
+        assert_eq!(service.get_is_active(cid).await, Ok(true));
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let service = service(3);
+        assert_eq!(service.snapshot().await, Ok(vec![]));
+
+        for id in ["carol", "alice", "bob"] {
+            service.add_client_sync(Arc::from(id)).await.unwrap();
+        }
+        service.set_is_active_sync(Arc::from("bob"), true).await.unwrap();
+        assert_eq!(
+            service.snapshot().await,
+            Ok(vec![
+                (String::from("alice"), false),
+                (String::from("bob"), true),
+                (String::from("carol"), false),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scan() {
+        let service = service(3);
+        let ids = client_ids(25);
+        service.add_clients(ids.iter().map(String::as_str)).await.unwrap();
+        for id in &ids[..10] {
+            service.set_is_active_sync(id.as_str(), true).await.unwrap();
+        }
+
+        // chunks smaller than every bucket, so each sends several
+        let mut scanned: Vec<(String, bool)> = service.scan(2).await.unwrap().collect().await;
+        scanned.sort();
+        assert_eq!(scanned, service.snapshot().await.unwrap());
+        assert_eq!(scanned.iter().filter(|(_, is_active)| *is_active).count(), 10);
+
+        // a dropped scan lets the buckets carry on
+        drop(service.scan(1).await.unwrap());
+        assert_eq!(service.total_client_count().await, Ok(25));
+    }
+
+    #[tokio::test]
+    async fn test_add_client_is_idempotent() {
+        let service = service(2);
+        let cid: Arc<str> = Arc::from("client123");
+        assert_eq!(service.add_client_sync(cid.clone()).await, Ok(true));
+        service.set_is_active_sync(cid.clone(), true).await.unwrap();
+        service.set_meta(cid.clone(), "region", "eu").await.unwrap();
+
+        assert_eq!(service.add_client_sync(cid.clone()).await, Ok(false));
+        assert_eq!(service.get_is_active(cid.clone()).await, Ok(true));
+        assert_eq!(service.get_meta(cid, "region").await, Ok(Some(String::from("eu"))));
+        assert_eq!(service.total_client_count().await, Ok(1));
+    }
+
+    /// A path under the system temp dir, removed again when dropped.
+    struct TempPath(std::path::PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("unrust-{}-{name}", std::process::id())))
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_save_and_load_round_trip() {
+        let path = TempPath::new("gateway.bin");
+        let service = service(3);
+        for id in client_ids(20) {
+            service.add_client_sync(Arc::from(id)).await.unwrap();
+        }
+        service.set_is_active_sync(Arc::from("client7"), true).await.unwrap();
+        service.set_meta(Arc::from("client7"), "region", "eu").await.unwrap();
+        service.save(&path.0).await.unwrap();
+
+        // a different bucket count still finds every client
+        let restored = GatewayService::new(5);
+        restored.load(&path.0).await.unwrap();
+        assert_eq!(restored.snapshot().await, service.snapshot().await);
+        assert_eq!(restored.get_is_active(Arc::from("client7")).await, Ok(true));
+        assert_eq!(restored.get_meta(Arc::from("client7"), "region").await, Ok(Some(String::from("eu"))));
+    }
+
+    #[tokio::test]
+    async fn test_load_snapshot_larger_than_decode_limit() {
+        let path = TempPath::new("large.bin");
+        let count = crate::serde_util::DECODE_LIMIT / std::mem::size_of::<ClientRecord>() + 1;
+        let record = |i: usize| ClientRecord { client_id: format!("client{i}"), is_active: i.is_multiple_of(2), value: (), metadata: HashMap::new() };
+        GatewaySnapshot { clients: (0..count).map(record).collect() }.save(&path.0).unwrap();
+
+        let service = service(4);
+        service.load(&path.0).await.unwrap();
+        assert_eq!(service.snapshot().await.unwrap().len(), count);
+        assert_eq!(service.get_is_active(Arc::from(format!("client{}", count - 1))).await, Ok((count - 1).is_multiple_of(2)));
+    }
+
+    #[tokio::test]
+    async fn test_load_rejects_garbage() {
+        let path = TempPath::new("garbage.bin");
+        std::fs::write(&path.0, [0xff; 3]).unwrap();
+        let err = service(1).load(&path.0).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let missing = TempPath::new("missing.bin");
+        assert_eq!(service(1).load(&missing.0).await.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_rebalance_after_growing() {
+        let mut service = service(2);
+        let ids: Vec<Arc<str>> = client_ids(200).into_iter().map(Arc::from).collect();
+        for id in &ids {
+            service.add_client_sync(id.clone()).await.unwrap();
+            service.set_is_active_sync(id.clone(), true).await.unwrap();
+        }
+
+        service.add_bucket();
+        service.add_bucket();
+        let misses = count_misses(&service, &ids).await;
+        assert!(misses > 0);
+
+        assert_eq!(service.rebalance(2).await, Ok(misses));
+        assert_eq!(count_misses(&service, &ids).await, 0);
+        assert_eq!(service.total_client_count().await, Ok(200));
+        // nothing left out of place
+        assert_eq!(service.rebalance(4).await, Ok(0));
+    }
+
+    #[tokio::test]
+    async fn test_rebalance_keeps_newer_state() {
+        let mut service = service(1);
+        let ids: Vec<Arc<str>> = client_ids(50).into_iter().map(Arc::from).collect();
+        for id in &ids {
+            service.add_client_sync(id.clone()).await.unwrap();
+        }
+        service.add_bucket();
+        let moved = ids.iter().find(|id| service.get_bucket(id) == 1).unwrap().clone();
+        // re-added and activated in the new bucket before the rebalance
+        service.add_client_sync(moved.clone()).await.unwrap();
+        service.set_is_active_sync(moved.clone(), true).await.unwrap();
+
+        service.rebalance(1).await.unwrap();
+        assert_eq!(service.get_is_active(moved).await, Ok(true));
+        assert_eq!(service.total_client_count().await, Ok(50));
+    }
+
+    /// How many of `ids` (all added active) no longer read back active.
+    async fn count_misses(service: &GatewayService, ids: &[Arc<str>]) -> usize {
+        let mut misses = 0;
+        for id in ids {
+            if service.get_is_active(id.clone()).await != Ok(true) {
+                misses += 1;
+            }
+        }
+        misses
+    }
+
+    #[tokio::test]
+    async fn test_get_or_create() {
+        let service = service(3);
+        let cid: Arc<str> = Arc::from("client123");
+        assert_eq!(service.get_or_create(cid.clone()).await, Ok(false));
+        assert_eq!(service.total_client_count().await, Ok(1));
+
+        service.set_is_active_sync(cid.clone(), true).await.unwrap();
+        assert_eq!(service.get_or_create(cid.clone()).await, Ok(true));
+        assert_eq!(service.get_is_active(cid).await, Ok(true));
+        assert_eq!(service.total_client_count().await, Ok(1));
+    }
+
+    #[tokio::test]
+    async fn test_set_and_get_meta() {
+        let service = service(4);
+        let cid: Arc<str> = Arc::from("client123");
+        service.add_client_sync(cid.clone()).await.unwrap();
+
+        assert_eq!(service.set_meta(cid.clone(), "region", "eu-west").await, Ok(true));
+        assert_eq!(service.set_meta(cid.clone(), "protocol", "ws").await, Ok(true));
+        assert_eq!(service.set_meta(cid.clone(), "region", "us-east").await, Ok(true));
+        assert_eq!(service.get_meta(cid.clone(), "region").await, Ok(Some("us-east".to_string())));
+        assert_eq!(service.get_meta(cid.clone(), "protocol").await, Ok(Some("ws".to_string())));
+        assert_eq!(service.get_meta(cid.clone(), "name").await, Ok(None));
+
+        // metadata sits alongside is_active rather than replacing it
+        service.set_is_active_sync(cid.clone(), true).await.unwrap();
+        assert_eq!(service.get_is_active(cid).await, Ok(true));
+    }
+
+    #[tokio::test]
+    async fn test_meta_unknown_client() {
+        let service = service(2);
+        let cid: Arc<str> = Arc::from("ghost");
+        assert_eq!(service.set_meta(cid.clone(), "region", "eu-west").await, Ok(false));
+        assert_eq!(service.get_meta(cid, "region").await, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn test_u64_value_as_counter() {
+        let mut service = GatewayService::builder().value::<u64>().buckets(2).build();
+        let cid: Arc<str> = Arc::from("client123");
+        assert_eq!(service.get_value(cid.clone()).await, Ok(None));
+        assert_eq!(service.set_value(cid.clone(), 1).await, Ok(false));
+
+        service.add_client_sync(cid.clone()).await.unwrap();
+        assert_eq!(service.get_value(cid.clone()).await, Ok(Some(0)));
+        for _ in 0..5 {
+            let count = service.get_value(cid.clone()).await.unwrap().unwrap();
+            assert_eq!(service.set_value(cid.clone(), count + 1).await, Ok(true));
+        }
+        assert_eq!(service.get_value(cid.clone()).await, Ok(Some(5)));
+        // the value is independent of is_active
+        assert_eq!(service.get_is_active(cid.clone()).await, Ok(false));
+
+        // and moves with the client on a rebalance
+        service.add_bucket();
+        service.add_bucket();
+        service.rebalance(2).await.unwrap();
+        assert_eq!(service.get_value(cid).await, Ok(Some(5)));
+    }
+
+    #[tokio::test]
+    async fn test_value_survives_save_and_load() {
+        let path = TempPath::new("values.bin");
+        let service = GatewayService::builder().value::<String>().buckets(2).build();
+        let cid: Arc<str> = Arc::from("client123");
+        service.add_client_sync(cid.clone()).await.unwrap();
+        service.set_value(cid.clone(), String::from("token-abc")).await.unwrap();
+        service.save(&path.0).await.unwrap();
+
+        let restored = GatewayService::builder().value::<String>().buckets(3).build();
+        restored.load(&path.0).await.unwrap();
+        assert_eq!(restored.get_value(cid).await, Ok(Some(String::from("token-abc"))));
+    }
+
+    #[test]
+    fn test_gateway_get_is_active_through_shared_borrow() {
+        let mut gateway: Gateway = Gateway::new(None, broadcast::channel(1).0);
+        gateway.add_client(Arc::from("client123"));
+        gateway.set_state(Arc::from("client123"), ClientState::Active);
+
+        let shared = &gateway;
+        assert!(shared.get_is_active(Arc::from("client123")));
+        assert!(!shared.get_is_active(Arc::from("ghost")));
+    }
+
+    #[test]
+    fn test_gateway_keys_compare_by_content() {
+        let mut gateway: Gateway = Gateway::new(None, broadcast::channel(1).0);
+        gateway.add_client(Arc::from("client123"));
+        // a separately allocated id with the same text finds the same client
+        gateway.set_state(Arc::from(String::from("client123")), ClientState::Active);
+        assert!(gateway.get_is_active(Arc::from("client123")));
+        assert_eq!(gateway.clients.len(), 1);
+        assert!(gateway.remove_client(Arc::from(String::from("client123"))));
+        assert_eq!(gateway.client_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_remove_client() {
+        let service = service(1);
+        let cid: Arc<str> = Arc::from("client123");
+
+        service.add_client(cid.clone()).await.unwrap();
+        service.set_is_active(cid.clone(), true).await.unwrap();
+        assert!(service.get_is_active(cid.clone()).await.unwrap());
+
+        assert!(service.remove_client(cid.clone()).await.unwrap());
+        assert!(!service.get_is_active(cid.clone()).await.unwrap());
+        assert!(!service.remove_client(cid).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_get_is_active_receiver_dropped() {
+        let service = service(1);
+        let cid: Arc<str> = Arc::from("client123");
+        service.add_client(cid.clone()).await.unwrap();
+
+        let (sender, receiver) = oneshot::channel::<bool>();
+        drop(receiver);
+        service.send_command(cid.clone(), Commands::GetIsActive { client_id: cid.clone(), sender }).await.unwrap();
+
+        // the event loop survives a dropped reply channel
+        assert!(!service.get_is_active(cid).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_send_failed_when_bucket_dropped() {
+        let (tx, rx) = mpsc::channel::<Commands>(1);
+        drop(rx);
+        let service = raw_service(vec![tx], vec![]);
+        let cid: Arc<str> = Arc::from("client123");
+
+        assert_eq!(service.add_client(cid.clone()).await, Err(GatewayError::SendFailed));
+        assert_eq!(service.set_is_active(cid.clone(), true).await, Err(GatewayError::SendFailed));
+        assert_eq!(service.get_is_active(cid.clone()).await, Err(GatewayError::SendFailed));
+        assert_eq!(service.remove_client(cid).await, Err(GatewayError::SendFailed));
+    }
+
+    #[tokio::test]
+    async fn test_channel_capacity_backpressure() {
+        let service = GatewayService::with_capacity(2, 2);
+        assert!(service.stats().await.unwrap().iter().all(|stats| stats.max_capacity == 2));
+
+        // on the current-thread runtime the buckets can't drain until we yield
+        let bucket = service.get_bucket("a");
+        let ids: Vec<Arc<str>> = client_ids(100).into_iter().map(Arc::from).filter(|id: &Arc<str>| service.get_bucket(id) == bucket).collect();
+        assert_eq!(service.try_add_client(ids[0].clone()), Ok(()));
+        assert_eq!(service.try_add_client(ids[1].clone()), Ok(()));
+        assert_eq!(service.try_add_client(ids[2].clone()), Err(GatewayError::Full));
+
+        // once it has drained there is room again
+        service.add_client_sync(ids[3].clone()).await.unwrap();
+        assert_eq!(service.try_add_client(ids[4].clone()), Ok(()));
+        assert_eq!(service.total_client_count().await, Ok(4));
+    }
+
+    #[tokio::test]
+    async fn test_add_client_deadline() {
+        let (tx, mut rx) = mpsc::channel::<Commands>(1);
+        let service = raw_service(vec![tx], vec![]);
+        // a consumer that starts draining only after a while
+        let consumer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            let mut received = 0;
+            while rx.recv().await.is_some() {
+                received += 1;
+            }
+            received
+        });
+
+        let now = tokio::time::Instant::now();
+        assert_eq!(service.add_client_deadline("client1", now + Duration::from_secs(5)).await, Ok(()));
+        // the queue stays full past a short deadline
+        assert_eq!(service.add_client_deadline("client2", now + Duration::from_millis(20)).await, Err(GatewayError::Timeout));
+        // a long one outlasts the slow consumer
+        assert_eq!(service.add_client_deadline("client3", now + Duration::from_secs(5)).await, Ok(()));
+        assert!(now.elapsed() >= Duration::from_millis(300));
+
+        drop(service);
+        assert_eq!(consumer.await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_try_send_full() {
+        // a bucket nobody is draining
+        let (tx, _rx) = mpsc::channel::<Commands>(1);
+        let service = raw_service(vec![tx], vec![]);
+        let cid: Arc<str> = Arc::from("client123");
+
+        assert_eq!(service.try_add_client(cid.clone()), Ok(()));
+        assert_eq!(service.try_set_is_active(cid.clone(), true), Err(GatewayError::Full));
+        assert_eq!(service.try_add_client(cid), Err(GatewayError::Full));
+    }
+
+    #[tokio::test]
+    async fn test_try_send_closed() {
+        let (tx, rx) = mpsc::channel::<Commands>(1);
+        drop(rx);
+        let service = raw_service(vec![tx], vec![]);
+
+        assert_eq!(service.try_add_client(Arc::from("client123")), Err(GatewayError::SendFailed));
+    }
+
+    #[tokio::test]
+    async fn test_try_send_applies_commands() {
+        let service = service(2);
+        let cid: Arc<str> = Arc::from("client123");
+        service.try_add_client(cid.clone()).unwrap();
+        service.try_set_is_active(cid.clone(), true).unwrap();
+        assert!(service.get_is_active(cid).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_recv_failed_when_reply_dropped() {
+        let (tx, mut rx) = mpsc::channel::<Commands>(1);
+        // a bucket that accepts commands but never replies
+        tokio::spawn(async move { while rx.recv().await.is_some() {} });
+        let service = raw_service(vec![tx], vec![]);
+
+        assert_eq!(service.get_is_active(Arc::from("client123")).await, Err(GatewayError::RecvFailed));
+    }
+
+    fn key(i: u64) -> u64 {
+        DefaultBuildHasher::default().hash_one(i)
+    }
+
+    #[test]
+    fn test_jump_hash_in_range() {
+        for buckets in 1..32 {
+            for i in 0..1000 {
+                let b = jump_hash(key(i), buckets);
+                assert!((0..buckets).contains(&b));
+            }
+        }
+    }
+
+    #[test]
+    fn test_jump_hash_single_and_empty() {
+        for i in 0..100 {
+            assert_eq!(jump_hash(key(i), 1), 0);
+            assert_eq!(jump_hash(key(i), 0), 0);
+            assert_eq!(jump_hash(key(i), -5), 0);
+        }
+    }
+
+    #[test]
+    fn test_jump_hash_stable_on_grow() {
+        let keys = 10_000;
+        for n in 1..16 {
+            let mut moved = 0;
+            for i in 0..keys {
+                let before = jump_hash(key(i), n);
+                let after = jump_hash(key(i), n + 1);
+                if before != after {
+                    // keys only ever move into the new bucket
+                    assert_eq!(after, n);
+                    moved += 1;
+                }
+            }
+            let expected = keys as f64 / (n + 1) as f64;
+            let diff = (moved as f64 - expected).abs();
+            assert!(diff < expected * 0.2, "n={n} moved={moved} expected~{expected}");
+        }
+    }
+}
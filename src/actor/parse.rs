@@ -0,0 +1,151 @@
+use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use super::{GatewayError, GatewayService};
+
+/// A console command, parsed by `parse_command` from lines such as
+/// `set client123 true`. Unlike `Commands` it carries no reply channel;
+/// `run` makes the matching `GatewayService` call.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParsedCommand {
+    /// `add <client_id>`
+    Add(Arc<str>),
+    /// `set <client_id> <true|false>`
+    Set(Arc<str>, bool),
+    /// `get <client_id>`
+    Get(Arc<str>),
+    /// `remove <client_id>`
+    Remove(Arc<str>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+    Empty,
+    UnknownVerb(String),
+    MissingArgument { verb: &'static str, argument: &'static str },
+    TooManyArguments { verb: &'static str },
+    /// `set` takes `true` or `false`.
+    InvalidBool(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => f.write_str("empty command"),
+            ParseError::UnknownVerb(verb) => write!(f, "unknown command `{verb}`, expected add, set, get or remove"),
+            ParseError::MissingArgument { verb, argument } => write!(f, "`{verb}` is missing its {argument}"),
+            ParseError::TooManyArguments { verb } => write!(f, "too many arguments to `{verb}`"),
+            ParseError::InvalidBool(value) => write!(f, "expected `true` or `false`, got `{value}`"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses one whitespace-separated command line. Verbs are case-insensitive;
+/// client ids are taken as written.
+pub fn parse_command(line: &str) -> Result<ParsedCommand, ParseError> {
+    let mut words = line.split_whitespace();
+    let word = words.next().ok_or(ParseError::Empty)?;
+    let verb = match word.to_ascii_lowercase().as_str() {
+        "add" => "add",
+        "set" => "set",
+        "get" => "get",
+        "remove" => "remove",
+        _ => return Err(ParseError::UnknownVerb(word.to_string())),
+    };
+    let client_id: Arc<str> = Arc::from(words.next().ok_or(ParseError::MissingArgument { verb, argument: "client id" })?);
+    let parsed = match verb {
+        "add" => ParsedCommand::Add(client_id),
+        "get" => ParsedCommand::Get(client_id),
+        "remove" => ParsedCommand::Remove(client_id),
+        _ => {
+            let value = words.next().ok_or(ParseError::MissingArgument { verb, argument: "value" })?;
+            match value.to_ascii_lowercase().as_str() {
+                "true" => ParsedCommand::Set(client_id, true),
+                "false" => ParsedCommand::Set(client_id, false),
+                _ => return Err(ParseError::InvalidBool(value.to_string())),
+            }
+        }
+    };
+    if words.next().is_some() {
+        return Err(ParseError::TooManyArguments { verb });
+    }
+    Ok(parsed)
+}
+
+impl TryFrom<&str> for ParsedCommand {
+    type Error = ParseError;
+
+    fn try_from(line: &str) -> Result<Self, Self::Error> {
+        parse_command(line)
+    }
+}
+
+impl ParsedCommand {
+    /// Makes the matching `GatewayService` call and describes the outcome
+    /// the way a console would print it.
+    pub async fn run<S, V>(self, service: &GatewayService<S, V>) -> Result<String, GatewayError>
+    where
+        S: BuildHasher,
+        V: Clone + Default + Send + Sync + 'static,
+    {
+        Ok(match self {
+            ParsedCommand::Add(client_id) => {
+                let added = service.add_client_sync(client_id).await?;
+                String::from(if added { "added" } else { "already exists" })
+            }
+            ParsedCommand::Set(client_id, is_active) => {
+                service.set_is_active_sync(client_id, is_active).await?;
+                String::from("ok")
+            }
+            ParsedCommand::Get(client_id) => service.get_is_active(client_id).await?.to_string(),
+            ParsedCommand::Remove(client_id) => {
+                let removed = service.remove_client(client_id).await?;
+                String::from(if removed { "removed" } else { "not found" })
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn id(client_id: &str) -> Arc<str> {
+        Arc::from(client_id)
+    }
+
+    #[test]
+    fn test_parse_each_verb() {
+        assert_eq!(parse_command("add client123"), Ok(ParsedCommand::Add(id("client123"))));
+        assert_eq!(parse_command("set client123 true"), Ok(ParsedCommand::Set(id("client123"), true)));
+        assert_eq!(parse_command("set client123 FALSE"), Ok(ParsedCommand::Set(id("client123"), false)));
+        assert_eq!(parse_command("  GET   client123  "), Ok(ParsedCommand::Get(id("client123"))));
+        assert_eq!(ParsedCommand::try_from("remove client123"), Ok(ParsedCommand::Remove(id("client123"))));
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        assert_eq!(parse_command(""), Err(ParseError::Empty));
+        assert_eq!(parse_command("   "), Err(ParseError::Empty));
+        assert_eq!(parse_command("delete client123"), Err(ParseError::UnknownVerb(String::from("delete"))));
+        assert_eq!(parse_command("add"), Err(ParseError::MissingArgument { verb: "add", argument: "client id" }));
+        assert_eq!(parse_command("set client123"), Err(ParseError::MissingArgument { verb: "set", argument: "value" }));
+        assert_eq!(parse_command("set client123 yes"), Err(ParseError::InvalidBool(String::from("yes"))));
+        assert_eq!(parse_command("get client123 extra"), Err(ParseError::TooManyArguments { verb: "get" }));
+        assert_eq!(parse_command("set").unwrap_err().to_string(), "`set` is missing its client id");
+    }
+
+    #[tokio::test]
+    async fn test_run_against_service() {
+        let service = GatewayService::new(2);
+        let mut replies = vec![];
+        for line in ["add client123", "add client123", "set client123 true", "get client123", "remove client123", "remove client123"] {
+            replies.push(parse_command(line).unwrap().run(&service).await.unwrap());
+        }
+        assert_eq!(replies, ["added", "already exists", "ok", "true", "removed", "not found"]);
+    }
+}
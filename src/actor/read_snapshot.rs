@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use super::ClientId;
+
+/// The `is_active` flags a bucket last published, for
+/// `GatewayService::get_is_active_snapshot`. The event loop replaces the
+/// whole map after a batch of writes; readers clone the `Arc` under the
+/// lock and then look up without holding it, so they never wait on the
+/// loop, or the loop on them, for longer than that clone.
+#[derive(Default)]
+pub(crate) struct ReadSnapshot {
+    current: Mutex<Arc<HashMap<ClientId, bool>>>,
+}
+
+impl ReadSnapshot {
+    pub(crate) fn load(&self) -> Arc<HashMap<ClientId, bool>> {
+        // nothing can panic while holding the lock, but a poisoned one still
+        // holds a complete map
+        self.current.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub(crate) fn store(&self, snapshot: HashMap<ClientId, bool>) {
+        let snapshot = Arc::new(snapshot);
+        // the old map is dropped after the lock is released
+        let _old = std::mem::replace(&mut *self.current.lock().unwrap_or_else(|e| e.into_inner()), snapshot);
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_loaded_snapshot_outlives_store() {
+        let slot = ReadSnapshot::default();
+        assert!(slot.load().is_empty());
+
+        slot.store(HashMap::from([(ClientId::from("client1"), true)]));
+        let held = slot.load();
+        slot.store(HashMap::new());
+        assert_eq!(held.get("client1"), Some(&true));
+        assert!(slot.load().is_empty());
+    }
+}
@@ -0,0 +1,870 @@
+use std::collections::HashMap;
+
+/// The `no_std` primitives the generated checks call, and the error type
+/// they report with.
+pub use unrust_core::{__private, is_email, ValidateLen, ValidationError};
+pub use validate_macro::Validate;
+/// Used by `#[validate(pattern = "...")]`.
+#[cfg(feature = "pattern")]
+pub use validate_pattern::{Pattern, PatternError};
+
+/// What a derived `validate_map` returns.
+pub type FieldErrors = HashMap<String, Vec<String>>;
+
+/// Groups messages by `field`, keeping each field's messages in order; what
+/// a derived `validate_map` returns.
+pub fn errors_by_field(errors: Vec<ValidationError>) -> FieldErrors {
+    let mut map = FieldErrors::new();
+    for error in errors {
+        map.entry(error.field).or_default().push(error.message);
+    }
+    map
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn messages(result: Result<(), Vec<ValidationError>>) -> Result<(), Vec<String>> {
+        result.map_err(|errors| errors.iter().map(ToString::to_string).collect())
+    }
+
+    #[derive(Validate)]
+    struct User {
+        #[min_length = 3]
+        name: String,
+        #[validate(min_length = 3, max_length = 8)]
+        username: String,
+        #[max_length = 4]
+        pin: String,
+    }
+
+    fn user(name: &str, username: &str, pin: &str) -> User {
+        User { name: name.to_string(), username: username.to_string(), pin: pin.to_string() }
+    }
+
+    #[test]
+    fn test_validate_ok() {
+        assert_eq!(messages(user("Alice", "alice", "1234").validate()), Ok(()));
+        assert_eq!(messages(user("Ali", "ali", "").validate()), Ok(()));
+        assert_eq!(messages(user("Alice", "alice_bo", "1234").validate()), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_min_length() {
+        assert_eq!(
+            messages(user("Al", "alice", "1234").validate()),
+            Err(vec![String::from("name must be at least 3 characters long")])
+        );
+        assert_eq!(
+            messages(user("Alice", "al", "1234").validate()),
+            Err(vec![String::from("username must be at least 3 characters long")])
+        );
+    }
+
+    #[test]
+    fn test_validate_max_length() {
+        assert_eq!(
+            messages(user("Alice", "alice_boro", "1234").validate()),
+            Err(vec![String::from("username must be at most 8 characters long")])
+        );
+        assert_eq!(
+            messages(user("Alice", "alice", "12345").validate()),
+            Err(vec![String::from("pin must be at most 4 characters long")])
+        );
+    }
+
+    #[test]
+    fn test_validate_collects_all_errors() {
+        assert_eq!(
+            messages(user("Al", "al", "12345").validate()),
+            Err(vec![
+                String::from("name must be at least 3 characters long"),
+                String::from("username must be at least 3 characters long"),
+                String::from("pin must be at most 4 characters long"),
+            ])
+        );
+    }
+
+    #[derive(Validate)]
+    struct Order {
+        #[validate(range(min = 1, max = 10))]
+        quantity: u32,
+        #[validate(range(min = -10))]
+        offset: i64,
+        #[validate(range(max = 120))]
+        age: u8,
+    }
+
+    #[test]
+    fn test_validate_range_bounds() {
+        assert_eq!(messages(Order { quantity: 1, offset: -10, age: 120 }.validate()), Ok(()));
+        assert_eq!(messages(Order { quantity: 10, offset: 0, age: 0 }.validate()), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_range_below() {
+        assert_eq!(
+            messages(Order { quantity: 0, offset: -11, age: 30 }.validate()),
+            Err(vec![String::from("quantity must be at least 1"), String::from("offset must be at least -10")])
+        );
+    }
+
+    #[test]
+    fn test_validate_range_above() {
+        assert_eq!(
+            messages(Order { quantity: 11, offset: i64::MAX, age: 121 }.validate()),
+            Err(vec![String::from("quantity must be at most 10"), String::from("age must be at most 120")])
+        );
+    }
+
+    #[derive(Validate)]
+    struct Username(#[min_length = 3] String);
+
+    #[derive(Validate)]
+    struct Pair(#[validate(max_length = 2)] String, #[validate(range(min = 1))] u32);
+
+    #[test]
+    fn test_validate_newtype() {
+        assert_eq!(messages(Username(String::from("alice")).validate()), Ok(()));
+        assert_eq!(
+            messages(Username(String::from("al")).validate()),
+            Err(vec![String::from("0 must be at least 3 characters long")])
+        );
+    }
+
+    #[test]
+    fn test_validate_tuple_struct() {
+        assert_eq!(messages(Pair(String::from("ab"), 1).validate()), Ok(()));
+        assert_eq!(
+            messages(Pair(String::from("abc"), 0).validate()),
+            Err(vec![String::from("0 must be at most 2 characters long"), String::from("1 must be at least 1")])
+        );
+    }
+
+    #[derive(Validate)]
+    struct Team {
+        #[validate(non_empty)]
+        name: String,
+        #[validate(non_empty)]
+        members: Vec<String>,
+        #[validate(non_empty)]
+        roles: HashMap<String, String>,
+    }
+
+    #[test]
+    fn test_validate_non_empty() {
+        let team = Team {
+            name: String::from("core"),
+            members: vec![String::from("alice")],
+            roles: HashMap::from([(String::from("alice"), String::from("lead"))]),
+        };
+        assert_eq!(messages(team.validate()), Ok(()));
+
+        let team = Team { name: String::new(), members: vec![], roles: HashMap::new() };
+        assert_eq!(
+            messages(team.validate()),
+            Err(vec![
+                String::from("name must not be empty"),
+                String::from("members must not be empty"),
+                String::from("roles must not be empty"),
+            ])
+        );
+    }
+
+    fn no_spaces(value: &str) -> Result<(), String> {
+        if value.contains(' ') {
+            Err(format!("{value:?} must not contain spaces"))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[derive(Validate)]
+    struct Host {
+        #[validate(min_length = 1, with = "no_spaces")]
+        name: String,
+    }
+
+    #[test]
+    fn test_validate_with() {
+        assert_eq!(messages(Host { name: String::from("example.com") }.validate()), Ok(()));
+        assert_eq!(
+            messages(Host { name: String::from("example com") }.validate()),
+            Err(vec![String::from("\"example com\" must not contain spaces")])
+        );
+    }
+
+    #[derive(Validate)]
+    struct Contact {
+        #[validate(email)]
+        email: String,
+    }
+
+    #[test]
+    fn test_validate_email() {
+        for valid in ["a@b.co", "first.last+tag@mail.example.com"] {
+            assert_eq!(messages(Contact { email: valid.to_string() }.validate()), Ok(()), "{valid}");
+        }
+        for invalid in ["a@", "@b", "ab", "a@@b", "a@b", "a@@b.com", "a@.com", "a@b.", "a b@c.com", ""] {
+            assert_eq!(
+                messages(Contact { email: invalid.to_string() }.validate()),
+                Err(vec![String::from("email must be a valid email address")]),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[derive(Validate)]
+    struct Draft {
+        #[validate(min_length = 3)]
+        title: String,
+        #[validate(skip, min_length = 100)]
+        #[min_length = 100]
+        body: String,
+    }
+
+    #[test]
+    fn test_validate_skip() {
+        let draft = Draft { title: String::from("Hello"), body: String::from("short") };
+        assert!(draft.body.len() < 100);
+        assert_eq!(messages(draft.validate()), Ok(()));
+
+        let draft = Draft { title: String::from("Hi"), body: String::new() };
+        assert_eq!(messages(draft.validate()), Err(vec![String::from("title must be at least 3 characters long")]));
+    }
+
+    #[derive(Validate)]
+    struct Tagged<T, const N: usize>
+    where
+        T: Clone,
+    {
+        #[min_length = 3]
+        tag: String,
+        values: [T; N],
+    }
+
+    #[test]
+    fn test_validate_generic_struct() {
+        let tagged = Tagged { tag: String::from("abc"), values: [1u8, 2] };
+        assert_eq!(tagged.values.len(), 2);
+        assert_eq!(messages(tagged.validate()), Ok(()));
+        assert_eq!(
+            messages(Tagged { tag: String::from("ab"), values: ['x'] }.validate()),
+            Err(vec![String::from("tag must be at least 3 characters long")])
+        );
+    }
+
+    /// Counts words rather than bytes.
+    struct Words(String);
+
+    impl ValidateLen for Words {
+        fn validate_len(&self) -> usize {
+            self.0.split_whitespace().count()
+        }
+    }
+
+    #[derive(Validate)]
+    struct Post<'a> {
+        #[validate(min_length = 2, max_length = 4)]
+        payload: Vec<u8>,
+        #[validate(min_length = 3)]
+        summary: Words,
+        #[validate(non_empty)]
+        slug: &'a str,
+    }
+
+    #[test]
+    fn test_validate_len_on_other_types() {
+        let post = Post { payload: vec![1, 2, 3], summary: Words(String::from("a short post")), slug: "a" };
+        assert_eq!(messages(post.validate()), Ok(()));
+
+        let post = Post { payload: vec![1], summary: Words(String::from("tiny post")), slug: "" };
+        assert_eq!(
+            messages(post.validate()),
+            Err(vec![
+                String::from("payload must be at least 2 characters long"),
+                String::from("summary must be at least 3 characters long"),
+                String::from("slug must not be empty"),
+            ])
+        );
+    }
+
+    #[derive(Validate)]
+    #[validate(assert = "self.end >= self.start", message = "end must not be before start")]
+    #[validate(assert = "self.end <= self.start + 30")]
+    struct Booking {
+        #[validate(min_length = 1)]
+        guest: String,
+        start: u32,
+        end: u32,
+    }
+
+    #[derive(Validate)]
+    #[validate(fields_match(a = "password", b = "confirm_password"))]
+    #[validate(fields_match(a = "email", b = "confirm_email"), message = "emails differ")]
+    struct SignupForm {
+        #[validate(min_length = 8)]
+        password: String,
+        confirm_password: String,
+        email: String,
+        confirm_email: String,
+    }
+
+    #[test]
+    fn test_validate_fields_match() {
+        let signup = SignupForm {
+            password: String::from("hunter22"),
+            confirm_password: String::from("hunter22"),
+            email: String::from("ann@example.com"),
+            confirm_email: String::from("ann@example.com"),
+        };
+        assert_eq!(messages(signup.validate()), Ok(()));
+
+        let signup = SignupForm { confirm_password: String::from("hunter23"), confirm_email: String::from("bob@example.com"), ..signup };
+        let errors = signup.validate().unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| (e.field.as_str(), e.code, e.message.as_str())).collect::<Vec<_>>(),
+            vec![
+                ("confirm_password", "fields_match", "password and confirm_password must match"),
+                ("confirm_email", "fields_match", "emails differ"),
+            ]
+        );
+    }
+
+    #[derive(Validate)]
+    struct Trip {
+        #[validate(nested)]
+        booking: Booking,
+    }
+
+    #[test]
+    fn test_validate_struct_assert() {
+        let booking = Booking { guest: String::from("Ann"), start: 10, end: 12 };
+        assert_eq!(messages(booking.validate()), Ok(()));
+
+        let booking = Booking { guest: String::new(), start: 12, end: 10 };
+        let errors = booking.validate().unwrap_err();
+        assert_eq!(
+            errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                String::from("guest must be at least 1 characters long"),
+                String::from("end must not be before start"),
+            ]
+        );
+        assert_eq!((errors[1].field.as_str(), errors[1].code), ("", "assert"));
+
+        let booking = Booking { guest: String::from("Ann"), start: 1, end: 40 };
+        assert_eq!(
+            messages(booking.validate()),
+            Err(vec![String::from("assertion failed: self.end <= self.start + 30")])
+        );
+    }
+
+    #[test]
+    fn test_validate_struct_assert_nested() {
+        let errors = Trip { booking: Booking { guest: String::from("Ann"), start: 5, end: 1 } }.validate().unwrap_err();
+        assert_eq!(errors[0].field, "booking");
+        assert_eq!(errors[0].to_string(), "booking: end must not be before start");
+    }
+
+    #[derive(Validate)]
+    struct Comment {
+        #[validate(trim, min_length = 1, max_length = 5)]
+        trimmed: String,
+        #[validate(min_length = 1)]
+        raw: String,
+        #[validate(trim, non_empty)]
+        author: String,
+    }
+
+    #[test]
+    fn test_validate_trim() {
+        let comment = Comment { trimmed: String::from("  hello  "), raw: String::from("  "), author: String::from(" a ") };
+        assert_eq!(messages(comment.validate()), Ok(()));
+
+        let comment = Comment { trimmed: String::from("   "), raw: String::from("   "), author: String::from("\t") };
+        assert_eq!(
+            messages(comment.validate()),
+            Err(vec![
+                String::from("trimmed must be at least 1 characters long"),
+                String::from("author must not be empty"),
+            ])
+        );
+        // the field itself is left untouched
+        assert_eq!(comment.trimmed, "   ");
+    }
+
+    #[cfg(feature = "pattern")]
+    #[derive(Validate)]
+    struct Page {
+        #[validate(pattern = "^[a-z0-9-]+$")]
+        slug: String,
+        #[validate(pattern = r"^\d{3}-\d{4}$", pattern = "^555")]
+        phone: String,
+    }
+
+    #[cfg(feature = "pattern")]
+    #[test]
+    fn test_validate_pattern() {
+        let page = Page { slug: String::from("hello-world"), phone: String::from("555-1234") };
+        assert_eq!(messages(page.validate()), Ok(()));
+
+        let page = Page { slug: String::from("Hello World"), phone: String::from("556-12345") };
+        assert_eq!(
+            messages(page.validate()),
+            Err(vec![
+                String::from("slug must match the pattern ^[a-z0-9-]+$"),
+                String::from("phone must match the pattern ^\\d{3}-\\d{4}$"),
+                String::from("phone must match the pattern ^555"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_validate_map() {
+        assert_eq!(Comment { trimmed: String::from("ok"), raw: String::from("ok"), author: String::from("a") }.validate_map(), Ok(()));
+
+        let booking = Booking { guest: String::new(), start: 50, end: 10 };
+        let map = booking.validate_map().unwrap_err();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["guest"], vec![String::from("guest must be at least 1 characters long")]);
+        assert_eq!(map[""], vec![String::from("end must not be before start")]);
+
+        let map = user("Al", "al", "12345").validate_map().unwrap_err();
+        assert_eq!(map["name"], vec![String::from("name must be at least 3 characters long")]);
+        assert_eq!(map["username"], vec![String::from("username must be at least 3 characters long")]);
+        assert_eq!(map["pin"], vec![String::from("pin must be at most 4 characters long")]);
+    }
+
+    #[derive(Validate)]
+    struct Handle {
+        #[validate(min_length = 5, with = "no_spaces")]
+        name: String,
+        #[validate(non_empty)]
+        bio: String,
+    }
+
+    #[test]
+    fn test_validate_map_groups_errors_per_field() {
+        let map = Handle { name: String::from("a b"), bio: String::new() }.validate_map().unwrap_err();
+        assert_eq!(map.len(), 2);
+        assert_eq!(
+            map["name"],
+            vec![
+                String::from("name must be at least 5 characters long"),
+                String::from("\"a b\" must not contain spaces"),
+            ]
+        );
+        assert_eq!(map["bio"], vec![String::from("bio must not be empty")]);
+    }
+
+    #[derive(Validate)]
+    struct Ticket {
+        #[validate(one_of = ["open", "closed"])]
+        status: String,
+        #[validate(one_of = [1, 2, 3])]
+        priority: u8,
+    }
+
+    #[test]
+    fn test_validate_one_of() {
+        let ticket = Ticket { status: String::from("open"), priority: 2 };
+        assert_eq!(messages(ticket.validate()), Ok(()));
+
+        let ticket = Ticket { status: String::from("pending"), priority: 5 };
+        assert_eq!(
+            messages(ticket.validate()),
+            Err(vec![
+                String::from("status must be one of [\"open\", \"closed\"]"),
+                String::from("priority must be one of [1, 2, 3]"),
+            ])
+        );
+    }
+
+    #[derive(Validate)]
+    struct Feed {
+        #[validate(starts_with = "https://", min_length = 12)]
+        url: String,
+        #[validate(ends_with = ".json")]
+        file: String,
+    }
+
+    #[test]
+    fn test_validate_starts_with() {
+        let feed = Feed { url: String::from("https://example.com"), file: String::from("feed.json") };
+        assert_eq!(messages(feed.validate()), Ok(()));
+
+        let feed = Feed { url: String::from("http://x.io"), ..feed };
+        assert_eq!(
+            messages(feed.validate()),
+            Err(vec![
+                String::from("url must be at least 12 characters long"),
+                String::from("url must start with \"https://\""),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_validate_ends_with() {
+        let feed = Feed { url: String::from("https://example.com"), file: String::from("data.json") };
+        assert_eq!(messages(feed.validate()), Ok(()));
+
+        let feed = Feed { file: String::from("feed.xml"), ..feed };
+        assert_eq!(messages(feed.validate()), Err(vec![String::from("file must end with \".json\"")]));
+    }
+
+    #[derive(Validate)]
+    struct Playlist {
+        #[validate(min_items = 1, max_items = 3)]
+        tracks: Vec<String>,
+    }
+
+    #[test]
+    fn test_validate_items() {
+        let tracks = |n: usize| (0..n).map(|i| format!("track{i}")).collect::<Vec<_>>();
+        for n in 1..=3 {
+            assert_eq!(messages(Playlist { tracks: tracks(n) }.validate()), Ok(()), "{n}");
+        }
+        let errors = Playlist { tracks: vec![] }.validate().unwrap_err();
+        assert_eq!(errors[0].code, "min_items");
+        assert_eq!(errors[0].message, "tracks must contain at least 1 items");
+        assert_eq!(
+            messages(Playlist { tracks: tracks(4) }.validate()),
+            Err(vec![String::from("tracks must contain at most 3 items")])
+        );
+    }
+
+    #[derive(Validate)]
+    enum Payload {
+        Text(#[validate(min_length = 1, max_length = 5)] String),
+        Batch {
+            #[validate(min_items = 1)]
+            items: Vec<Playlist>,
+            #[validate(nested)]
+            first: Option<Playlist>,
+        },
+        Ping,
+    }
+
+    #[test]
+    fn test_validate_enum() {
+        assert_eq!(messages(Payload::Text(String::from("hi")).validate()), Ok(()));
+        assert_eq!(
+            messages(Payload::Text(String::from("hello world")).validate()),
+            Err(vec![String::from("0 must be at most 5 characters long")])
+        );
+        assert_eq!(messages(Payload::Ping.validate()), Ok(()));
+
+        let playlist = Playlist { tracks: vec![String::from("intro")] };
+        let batch = Payload::Batch { items: vec![], first: Some(Playlist { tracks: vec![] }) };
+        assert_eq!(
+            messages(batch.validate()),
+            Err(vec![
+                String::from("items must contain at least 1 items"),
+                String::from("first.tracks must contain at least 1 items"),
+            ])
+        );
+        let batch = Payload::Batch { items: vec![playlist], first: None };
+        assert_eq!(messages(batch.validate()), Ok(()));
+    }
+
+    #[derive(Validate)]
+    #[validate(mode = "collect")]
+    struct CollectForm {
+        #[validate(min_length = 3, email)]
+        email: String,
+        #[validate(each(min_length = 1))]
+        tags: Vec<String>,
+    }
+
+    #[derive(Validate)]
+    #[validate(mode = "fail_fast")]
+    #[validate(assert = "self.tags.len() < 3", message = "too many tags")]
+    struct FailFastForm {
+        #[validate(min_length = 3, email)]
+        email: String,
+        #[validate(each(min_length = 1))]
+        tags: Vec<String>,
+    }
+
+    #[test]
+    fn test_validate_modes() {
+        let tags = vec![String::new(), String::new(), String::new()];
+        let collected = CollectForm { email: String::from("a"), tags: tags.clone() }.validate().unwrap_err();
+        assert_eq!(collected.iter().map(|e| e.code).collect::<Vec<_>>(), ["min_length", "email", "min_length", "min_length", "min_length"]);
+
+        let form = FailFastForm { email: String::from("a"), tags };
+        assert_eq!(messages(form.validate()), Err(vec![String::from("email must be at least 3 characters long")]));
+        // the first failing element stops the whole check
+        let form = FailFastForm { email: String::from("ann@example.com"), ..form };
+        assert_eq!(messages(form.validate()), Err(vec![String::from("tags[0] must be at least 1 characters long")]));
+        let form = FailFastForm { tags: vec![String::from("a"); 3], ..form };
+        assert_eq!(messages(form.validate()), Err(vec![String::from("too many tags")]));
+        assert_eq!(messages(FailFastForm { tags: vec![], ..form }.validate()), Ok(()));
+    }
+
+    #[derive(Validate)]
+    struct Account {
+        #[validate(rename = "userName", min_length = 3)]
+        user_name: String,
+        #[validate(rename = "tagList", each(min_length = 1))]
+        tag_list: Vec<String>,
+        #[validate(rename = "homeHost", nested)]
+        home_host: Host,
+    }
+
+    #[test]
+    fn test_validate_rename() {
+        let account = Account { user_name: String::from("al"), tag_list: vec![String::new()], home_host: Host { name: String::new() } };
+        let errors = account.validate().unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| (e.field.as_str(), e.message.as_str())).collect::<Vec<_>>(),
+            vec![
+                ("userName", "userName must be at least 3 characters long"),
+                ("tagList[0]", "tagList[0] must be at least 1 characters long"),
+                ("homeHost.name", "homeHost.name must be at least 1 characters long"),
+            ]
+        );
+        assert!(account.validate_map().unwrap_err().contains_key("userName"));
+    }
+
+    #[derive(Validate)]
+    struct Profile {
+        #[validate(min_length = 3, trim)]
+        nickname: Option<String>,
+        #[validate(range(min = 13))]
+        age: Option<u32>,
+        #[validate(nested)]
+        address: Option<Host>,
+    }
+
+    #[test]
+    fn test_validate_option_fields() {
+        let profile = Profile { nickname: None, age: None, address: None };
+        assert_eq!(messages(profile.validate()), Ok(()));
+
+        let profile = Profile { nickname: Some(String::from("sam")), age: Some(30), address: None };
+        assert_eq!(messages(profile.validate()), Ok(()));
+
+        let profile = Profile { nickname: Some(String::from(" al ")), age: Some(9), address: None };
+        assert_eq!(
+            messages(profile.validate()),
+            Err(vec![String::from("nickname must be at least 3 characters long"), String::from("age must be at least 13")])
+        );
+    }
+
+    #[test]
+    fn test_validate_option_nested() {
+        let profile = Profile { nickname: None, age: None, address: Some(Host { name: String::from("a b") }) };
+        assert_eq!(messages(profile.validate()), Err(vec![String::from("address.name: \"a b\" must not contain spaces")]));
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum AppError {
+        Invalid { field: String, message: String },
+    }
+
+    impl From<ValidationError> for AppError {
+        fn from(e: ValidationError) -> Self {
+            AppError::Invalid { field: e.field, message: e.message }
+        }
+    }
+
+    fn register(user: &User) -> Result<&'static str, AppError> {
+        user.validate_into::<AppError>()?;
+        Ok("registered")
+    }
+
+    #[test]
+    fn test_validate_into_custom_error() {
+        assert_eq!(register(&user("Alice", "alice", "1234")), Ok("registered"));
+        // the first failure, in field order
+        assert_eq!(
+            register(&user("Al", "al", "1234")),
+            Err(AppError::Invalid { field: String::from("name"), message: String::from("name must be at least 3 characters long") })
+        );
+    }
+
+    #[derive(Validate)]
+    struct Roster {
+        #[validate(unique)]
+        members: Vec<String>,
+        #[validate(unique(case_insensitive))]
+        handles: Vec<String>,
+        #[validate(unique)]
+        numbers: Vec<u32>,
+    }
+
+    fn roster(members: &[&str], handles: &[&str]) -> Roster {
+        Roster {
+            members: members.iter().map(|name| name.to_string()).collect(),
+            handles: handles.iter().map(|name| name.to_string()).collect(),
+            numbers: vec![7, 10, 23],
+        }
+    }
+
+    #[test]
+    fn test_validate_unique() {
+        assert_eq!(messages(roster(&["Alice", "Bob"], &["alice", "bob"]).validate()), Ok(()));
+        // only exact repeats count without case_insensitive
+        assert_eq!(messages(roster(&["Alice", "alice"], &[]).validate()), Ok(()));
+        assert_eq!(
+            messages(roster(&["Alice", "Bob", "Alice", "Bob"], &[]).validate()),
+            Err(vec![String::from("members must not contain duplicates, found \"Alice\"")])
+        );
+
+        let mut numbers = roster(&[], &[]);
+        numbers.numbers.push(10);
+        assert_eq!(messages(numbers.validate()), Err(vec![String::from("numbers must not contain duplicates, found 10")]));
+    }
+
+    #[test]
+    fn test_validate_unique_case_insensitive() {
+        assert_eq!(
+            messages(roster(&[], &["alice", "Bob", "BOB"]).validate()),
+            Err(vec![String::from("handles must not contain duplicates, found \"BOB\"")])
+        );
+    }
+
+    #[derive(Validate)]
+    struct Article {
+        #[validate(max_length = 3, each(min_length = 2, max_length = 8))]
+        tags: Vec<String>,
+        #[validate(each(range(max = 5)))]
+        ratings: Vec<u8>,
+    }
+
+    #[test]
+    fn test_validate_each() {
+        let article = Article { tags: vec![String::from("rust"), String::from("async")], ratings: vec![5, 3] };
+        assert_eq!(messages(article.validate()), Ok(()));
+
+        let article = Article { tags: vec![String::from("rust"), String::from("x"), String::from("concurrency")], ratings: vec![4, 6] };
+        let errors = article.validate().unwrap_err();
+        assert_eq!(
+            errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                "tags[1] must be at least 2 characters long",
+                "tags[2] must be at most 8 characters long",
+                "ratings[1] must be at most 5",
+            ]
+        );
+        assert_eq!(errors[0].field, "tags[1]");
+    }
+
+    #[cfg(feature = "pattern")]
+    #[derive(Validate)]
+    struct Settings {
+        #[validate(each_key(pattern = "^[a-z_]+$"), each_value(non_empty))]
+        options: HashMap<String, String>,
+    }
+
+    #[cfg(feature = "pattern")]
+    #[test]
+    fn test_validate_each_key_and_value() {
+        let mut options = HashMap::new();
+        options.insert(String::from("theme"), String::from("dark"));
+        options.insert(String::from("font_size"), String::from("12"));
+        assert_eq!(messages(Settings { options: options.clone() }.validate()), Ok(()));
+
+        options.insert(String::from("theme"), String::new());
+        assert_eq!(
+            messages(Settings { options: options.clone() }.validate()),
+            Err(vec![String::from("options[\"theme\"] must not be empty")])
+        );
+
+        options.insert(String::from("theme"), String::from("dark"));
+        options.insert(String::from("Line Height"), String::from("1.5"));
+        let errors = Settings { options }.validate().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, "options key \"Line Height\"");
+        assert_eq!(errors[0].message, "options key \"Line Height\" must match the pattern ^[a-z_]+$");
+    }
+
+    /// Pretends to look the name up in a store of taken usernames.
+    async fn username_available(username: &String) -> Result<(), String> {
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        if ["admin", "root"].contains(&username.as_str()) {
+            Err(format!("{username} is already taken"))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[derive(Validate)]
+    struct Registration {
+        #[validate(min_length = 5, with_async = "username_available")]
+        username: String,
+    }
+
+    #[tokio::test]
+    async fn test_validate_async() {
+        let registration = Registration { username: String::from("alice") };
+        assert_eq!(messages(registration.validate_async().await), Ok(()));
+
+        // the sync validate skips the async lookup
+        let taken = Registration { username: String::from("admin") };
+        assert_eq!(messages(taken.validate()), Ok(()));
+        assert_eq!(messages(taken.validate_async().await), Err(vec![String::from("admin is already taken")]));
+
+        let both = Registration { username: String::from("root") };
+        let errors = both.validate_async().await.unwrap_err();
+        let codes: Vec<&str> = errors.iter().map(|e| e.code).collect();
+        assert_eq!(codes, vec!["min_length", "with_async"]);
+    }
+
+    #[derive(Validate)]
+    struct Signup {
+        #[validate(nested)]
+        user: User,
+        #[validate(nested)]
+        hosts: Vec<Host>,
+    }
+
+    #[test]
+    fn test_validate_nested() {
+        let signup = Signup { user: user("Alice", "alice", "1234"), hosts: vec![] };
+        assert_eq!(messages(signup.validate()), Ok(()));
+
+        let signup = Signup { user: user("Al", "alice", "12345"), hosts: vec![] };
+        assert_eq!(
+            messages(signup.validate()),
+            Err(vec![
+                String::from("user.name must be at least 3 characters long"),
+                String::from("user.pin must be at most 4 characters long"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_validate_nested_vec() {
+        let signup = Signup {
+            user: user("Alice", "alice", "1234"),
+            hosts: vec![Host { name: String::from("a.com") }, Host { name: String::new() }],
+        };
+        assert_eq!(
+            messages(signup.validate()),
+            Err(vec![String::from("hosts[1].name must be at least 1 characters long")])
+        );
+    }
+
+    #[test]
+    fn test_validation_error_field_and_code() {
+        let errors = Order { quantity: 0, offset: 0, age: 30 }.validate().unwrap_err();
+        assert_eq!(errors, vec![ValidationError::new("quantity", "range", "quantity must be at least 1")]);
+
+        let errors = Host { name: String::from("a b") }.validate().unwrap_err();
+        assert_eq!((errors[0].field.as_str(), errors[0].code), ("name", "with"));
+
+        let signup = Signup {
+            user: user("Al", "alice", "1234"),
+            hosts: vec![Host { name: String::from("a b") }],
+        };
+        let errors = signup.validate().unwrap_err();
+        let fields: Vec<(&str, &str)> = errors.iter().map(|e| (e.field.as_str(), e.code)).collect();
+        assert_eq!(fields, vec![("user.name", "min_length"), ("hosts[0].name", "with")]);
+        assert_eq!(errors[1].to_string(), "hosts[0].name: \"a b\" must not contain spaces");
+    }
+}
@@ -1,24 +1,20 @@
-use bincode::{config, Decode, Encode};
-
-#[derive(Encode, Decode, PartialEq, Debug)]
-struct User {
-    fname: String,
-    lname: String,
-}
-
-#[derive(Encode, Decode, PartialEq, Debug)]
-struct Users(Vec<User>);
+use unrust::serde_util::{decode, encode, User, Users};
 
 fn main() {
-    let config = config::standard();
-
     let users = Users(vec![User { fname: String::from("Sankar"), lname: String::from("Boro") }, User { fname: String::from("Arun"), lname: String::from("Das") }]);
 
-    let encoded: Vec<u8> = bincode::encode_to_vec(&users, config).unwrap();
+    let encoded: Vec<u8> = match encode(&users) {
+        Ok(encoded) => encoded,
+        Err(e) => {
+            eprintln!("encode failed: {e}");
+            return;
+        }
+    };
 
     println!("encoded: {:?}", encoded);
 
-    let (decoded, len): (Users, usize) = bincode::decode_from_slice(&encoded[..], config).unwrap();
-
-    println!("decoded: {:?}", decoded);
-}
\ No newline at end of file
+    match decode::<Users>(&encoded) {
+        Ok(decoded) => println!("decoded: {:?}", decoded),
+        Err(e) => eprintln!("decode failed: {e}"),
+    }
+}
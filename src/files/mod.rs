@@ -11,7 +11,7 @@ where P: AsRef<Path>
     Ok(contents)
 }
 
-fn create_file<P>(path: P, data: &Vec<u8>) -> std::io::Result<()> 
+fn create_file<P>(path: P, data: &[u8]) -> std::io::Result<()> 
 where P: AsRef<Path>
 {
     let mut file = File::create(path)?;
@@ -1,5 +1,11 @@
+extern crate self as unrust;
+
+#[allow(dead_code)]
 mod files;
-mod app_macro;
+pub mod app_macro;
+#[allow(dead_code)]
 mod bitwise;
 
 pub mod actor;
+pub mod serde_util;
+pub mod smart_pointer;
@@ -0,0 +1,103 @@
+use std::fmt;
+
+use bincode::error::{DecodeError, EncodeError};
+use bincode::{Decode, Encode};
+
+use super::{decode, encode};
+
+/// A bincode message tagged with the version of its payload's format. The
+/// payload stays encoded, so a reader can look at `version` and skip
+/// envelopes it does not understand without failing on their contents.
+#[derive(Encode, Decode, Clone, PartialEq, Debug)]
+pub struct Envelope {
+    pub version: u16,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum EnvelopeError {
+    Decode(DecodeError),
+    /// The envelope carries a version the reader was not written for.
+    UnsupportedVersion { found: u16, expected: u16 },
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvelopeError::Decode(e) => write!(f, "envelope decode error: {e}"),
+            EnvelopeError::UnsupportedVersion { found, expected } => {
+                write!(f, "unsupported envelope version {found}, expected {expected}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EnvelopeError {}
+
+impl From<DecodeError> for EnvelopeError {
+    fn from(e: DecodeError) -> Self {
+        EnvelopeError::Decode(e)
+    }
+}
+
+/// Encodes `value` and wraps it in an `Envelope` tagged with `version`.
+pub fn encode_versioned<T: Encode>(value: &T, version: u16) -> Result<Vec<u8>, EncodeError> {
+    encode(&Envelope { version, payload: encode(value)? })
+}
+
+/// Decodes an `Envelope` from `bytes` and, if it carries `version`, its
+/// payload as a `T`. Any other version is `UnsupportedVersion`, and its
+/// payload is left alone.
+pub fn decode_versioned<T: Decode<()>>(bytes: &[u8], version: u16) -> Result<T, EnvelopeError> {
+    let envelope: Envelope = decode(bytes)?;
+    if envelope.version != version {
+        return Err(EnvelopeError::UnsupportedVersion { found: envelope.version, expected: version });
+    }
+    Ok(decode(&envelope.payload)?)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::serde_util::{decode_all, User};
+
+    fn user() -> User {
+        User { fname: String::from("Sankar"), lname: String::from("Boro") }
+    }
+
+    #[test]
+    fn test_versioned_round_trip() {
+        let bytes = encode_versioned(&user(), 3).unwrap();
+        assert_eq!(decode_versioned::<User>(&bytes, 3).unwrap(), user());
+
+        let envelope: Envelope = decode(&bytes).unwrap();
+        assert_eq!(envelope.version, 3);
+        assert_eq!(decode::<User>(&envelope.payload).unwrap(), user());
+    }
+
+    #[test]
+    fn test_unexpected_version_rejected() {
+        let bytes = encode_versioned(&user(), 2).unwrap();
+        assert!(matches!(
+            decode_versioned::<User>(&bytes, 1),
+            Err(EnvelopeError::UnsupportedVersion { found: 2, expected: 1 })
+        ));
+        assert!(matches!(decode_versioned::<User>(&bytes[..2], 2), Err(EnvelopeError::Decode(_))));
+    }
+
+    #[test]
+    fn test_reader_skips_unknown_versions() {
+        let mut bytes = encode_versioned(&user(), 1).unwrap();
+        // a newer writer's payload need not be a `User` at all
+        bytes.extend(encode_versioned(&(1u8, 2u64, "new"), 2).unwrap());
+        bytes.extend(encode_versioned(&user(), 1).unwrap());
+
+        let users: Vec<User> = decode_all::<Envelope>(&bytes)
+            .map(Result::unwrap)
+            .filter(|envelope| envelope.version == 1)
+            .map(|envelope| decode(&envelope.payload).unwrap())
+            .collect();
+        assert_eq!(users, [user(), user()]);
+    }
+}
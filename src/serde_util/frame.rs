@@ -0,0 +1,141 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use bincode::error::{DecodeError, EncodeError};
+use bincode::{Decode, Encode};
+
+use super::{decode, encode};
+
+/// Frames larger than this are rejected unless the codec is configured otherwise.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 8 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum FrameError {
+    Io(io::Error),
+    Encode(EncodeError),
+    Decode(DecodeError),
+    /// The frame's payload is bigger than the codec's `max_frame_size`.
+    TooLarge { len: usize, max: usize },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Io(e) => write!(f, "frame io error: {e}"),
+            FrameError::Encode(e) => write!(f, "frame encode error: {e}"),
+            FrameError::Decode(e) => write!(f, "frame decode error: {e}"),
+            FrameError::TooLarge { len, max } => write!(f, "frame of {len} bytes exceeds the {max} byte limit"),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+/// Length-prefixed framing: each frame is a 4-byte big-endian payload length
+/// followed by the bincode-encoded payload.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameCodec {
+    pub max_frame_size: usize,
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self { max_frame_size: DEFAULT_MAX_FRAME_SIZE }
+    }
+}
+
+impl FrameCodec {
+    pub fn new(max_frame_size: usize) -> Self {
+        Self { max_frame_size }
+    }
+
+    pub fn write_frame<T: Encode, W: Write>(&self, value: &T, writer: &mut W) -> Result<(), FrameError> {
+        let payload = encode(value).map_err(FrameError::Encode)?;
+        let len = self.check_len(payload.len())?;
+        writer.write_all(&len.to_be_bytes())?;
+        writer.write_all(&payload)?;
+        Ok(())
+    }
+
+    /// Reads the next frame. The length prefix is checked against
+    /// `max_frame_size` before any payload is buffered.
+    pub fn read_frame<T: Decode<()>, R: Read>(&self, reader: &mut R) -> Result<T, FrameError> {
+        let mut prefix = [0u8; 4];
+        reader.read_exact(&mut prefix)?;
+        let len = u32::from_be_bytes(prefix) as usize;
+        self.check_len(len)?;
+
+        let mut payload = vec![0u8; len];
+        reader.read_exact(&mut payload)?;
+        decode(&payload).map_err(FrameError::Decode)
+    }
+
+    fn check_len(&self, len: usize) -> Result<u32, FrameError> {
+        let too_large = FrameError::TooLarge { len, max: self.max_frame_size };
+        if len > self.max_frame_size {
+            return Err(too_large);
+        }
+        u32::try_from(len).map_err(|_| too_large)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::serde_util::User;
+    use std::io::Cursor;
+
+    fn user(fname: &str, lname: &str) -> User {
+        User { fname: fname.to_string(), lname: lname.to_string() }
+    }
+
+    #[test]
+    fn test_two_frames_round_trip() {
+        let codec = FrameCodec::default();
+        let mut cursor = Cursor::new(Vec::new());
+        codec.write_frame(&user("Sankar", "Boro"), &mut cursor).unwrap();
+        codec.write_frame(&user("Arun", "Das"), &mut cursor).unwrap();
+
+        cursor.set_position(0);
+        assert_eq!(codec.read_frame::<User, _>(&mut cursor).unwrap(), user("Sankar", "Boro"));
+        assert_eq!(codec.read_frame::<User, _>(&mut cursor).unwrap(), user("Arun", "Das"));
+        assert!(matches!(codec.read_frame::<User, _>(&mut cursor), Err(FrameError::Io(_))));
+    }
+
+    #[test]
+    fn test_read_rejects_oversized_prefix() {
+        let codec = FrameCodec::new(16);
+        let mut cursor = Cursor::new(u32::MAX.to_be_bytes().to_vec());
+        assert!(matches!(
+            codec.read_frame::<User, _>(&mut cursor),
+            Err(FrameError::TooLarge { len, max: 16 }) if len == u32::MAX as usize
+        ));
+    }
+
+    #[test]
+    fn test_write_rejects_oversized_payload() {
+        let codec = FrameCodec::new(4);
+        let mut cursor = Cursor::new(Vec::new());
+        assert!(matches!(
+            codec.write_frame(&user("Sankar", "Boro"), &mut cursor),
+            Err(FrameError::TooLarge { max: 4, .. })
+        ));
+        assert!(cursor.get_ref().is_empty());
+    }
+
+    #[test]
+    fn test_truncated_payload() {
+        let codec = FrameCodec::default();
+        let mut bytes = Vec::new();
+        codec.write_frame(&user("Sankar", "Boro"), &mut bytes).unwrap();
+        bytes.pop();
+        assert!(matches!(codec.read_frame::<User, _>(&mut Cursor::new(bytes)), Err(FrameError::Io(_))));
+    }
+}
@@ -0,0 +1,213 @@
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+
+use bincode::config::{self, Config};
+use bincode::error::{DecodeError, EncodeError};
+use bincode::{Decode, Encode};
+
+pub mod envelope;
+pub mod frame;
+mod user;
+
+pub use user::{User, Users};
+
+/// Encodes `value` with bincode's standard config.
+pub fn encode<T: Encode>(value: &T) -> Result<Vec<u8>, EncodeError> {
+    bincode::encode_to_vec(value, config::standard())
+}
+
+/// The most `decode`, `decode_all` and `decode_from` will allocate for one
+/// value. Without a limit a length prefix read from untrusted bytes sizes
+/// the allocation directly, which panics or aborts for a large enough claim
+/// instead of failing.
+pub const DECODE_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Decodes a `T` from the start of `bytes` with bincode's standard config,
+/// capped at `DECODE_LIMIT`. Safe to call on untrusted input: malformed
+/// bytes return an error and never panic.
+pub fn decode<T: Decode<()>>(bytes: &[u8]) -> Result<T, DecodeError> {
+    let (value, _len) = bincode::decode_from_slice(bytes, config::standard().with_limit::<DECODE_LIMIT>())?;
+    Ok(value)
+}
+
+/// Decodes back-to-back `T`s from `bytes`, e.g. records appended one after
+/// another with `encode`, until the input runs out. Trailing bytes that do
+/// not make up a whole `T` yield one error, after which the iterator ends.
+/// Each `T` is capped at `DECODE_LIMIT`, as with `decode`.
+pub fn decode_all<T: Decode<()>>(bytes: &[u8]) -> DecodeAll<'_, T> {
+    DecodeAll { bytes, marker: PhantomData }
+}
+
+/// The iterator returned by `decode_all`.
+pub struct DecodeAll<'a, T> {
+    bytes: &'a [u8],
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Decode<()>> Iterator for DecodeAll<'_, T> {
+    type Item = Result<T, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        match bincode::decode_from_slice(self.bytes, config::standard().with_limit::<DECODE_LIMIT>()) {
+            Ok((value, len)) => {
+                self.bytes = &self.bytes[len..];
+                Some(Ok(value))
+            }
+            Err(e) => {
+                self.bytes = &[];
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Streams `value` into `writer` with bincode's standard config, returning
+/// the number of bytes written.
+pub fn encode_into<T: Encode, W: Write>(value: &T, writer: &mut W) -> Result<usize, EncodeError> {
+    encode_into_with_config(value, writer, config::standard())
+}
+
+pub fn encode_into_with_config<T: Encode, W: Write, C: Config>(
+    value: &T,
+    writer: &mut W,
+    config: C,
+) -> Result<usize, EncodeError> {
+    bincode::encode_into_std_write(value, writer, config)
+}
+
+/// Reads one `T` from `reader` with bincode's standard config, capped at
+/// `DECODE_LIMIT`.
+pub fn decode_from<T: Decode<()>, R: Read>(reader: &mut R) -> Result<T, DecodeError> {
+    decode_from_with_config(reader, config::standard().with_limit::<DECODE_LIMIT>())
+}
+
+/// Like `decode_from` with a config of the caller's choosing. Nothing is
+/// capped unless `config` sets a limit, so give untrusted input one.
+pub fn decode_from_with_config<T: Decode<()>, R: Read, C: Config>(reader: &mut R, config: C) -> Result<T, DecodeError> {
+    bincode::decode_from_std_read(reader, config)
+}
+
+/// Renders `value` as JSON, e.g. for debugging what would go on the wire.
+#[cfg(feature = "serde")]
+pub fn to_json<T: serde::Serialize>(value: &T) -> serde_json::Result<String> {
+    serde_json::to_string(value)
+}
+
+#[cfg(feature = "serde")]
+pub fn from_json<T: serde::de::DeserializeOwned>(json: &str) -> serde_json::Result<T> {
+    serde_json::from_str(json)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::io::Cursor;
+
+    fn users() -> Users {
+        Users(vec![
+            User { fname: String::from("Sankar"), lname: String::from("Boro") },
+            User { fname: String::from("Arun"), lname: String::from("Das") },
+        ])
+    }
+
+    #[test]
+    fn test_user_round_trip() {
+        let user = User { fname: String::from("Sankar"), lname: String::from("Boro") };
+        let encoded = encode(&user).unwrap();
+        assert_eq!(decode::<User>(&encoded).unwrap(), user);
+    }
+
+    #[test]
+    fn test_users_round_trip() {
+        let users = users();
+        let encoded = encode(&users).unwrap();
+        assert_eq!(decode::<Users>(&encoded).unwrap(), users);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_round_trip() {
+        let json = to_json(&users()).unwrap();
+        assert_eq!(json, r#"[{"fname":"Sankar","lname":"Boro"},{"fname":"Arun","lname":"Das"}]"#);
+        assert_eq!(from_json::<Users>(&json).unwrap(), users());
+        // the derives sit alongside bincode's, which still round-trips
+        assert_eq!(decode::<Users>(&encode(&from_json::<Users>(&json).unwrap()).unwrap()).unwrap(), users());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_from_json_rejects_wrong_shape() {
+        assert!(from_json::<User>(r#"{"fname":"Sankar"}"#).is_err());
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let encoded = encode(&users()).unwrap();
+        assert!(decode::<Users>(&encoded[..encoded.len() - 1]).is_err());
+        assert!(decode::<Users>(&[]).is_err());
+    }
+
+    #[test]
+    fn test_decode_oversized_length() {
+        // a u64::MAX element count, which would otherwise size the Vec
+        let mut bytes = vec![0xfd];
+        bytes.extend(u64::MAX.to_le_bytes());
+        assert!(matches!(decode::<Users>(&bytes), Err(DecodeError::LimitExceeded)));
+        // one user whose first name claims u64::MAX bytes
+        bytes.insert(0, 0x01);
+        assert!(matches!(decode::<Users>(&bytes), Err(DecodeError::LimitExceeded)));
+
+        let mut records = decode_all::<Users>(&bytes);
+        assert!(matches!(records.next(), Some(Err(DecodeError::LimitExceeded))));
+        assert!(records.next().is_none());
+        assert!(matches!(decode_from::<Users, _>(&mut Cursor::new(&bytes)), Err(DecodeError::LimitExceeded)));
+    }
+
+    #[test]
+    fn test_decode_all() {
+        let mut bytes = vec![];
+        for user in users().0.iter().chain(&users().0[..1]) {
+            bytes.extend(encode(user).unwrap());
+        }
+        let decoded: Vec<User> = decode_all(&bytes).collect::<Result<_, _>>().unwrap();
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(decoded[..2], users().0[..]);
+        assert_eq!(decoded[2], users().0[0]);
+
+        assert_eq!(decode_all::<User>(&[]).count(), 0);
+    }
+
+    #[test]
+    fn test_decode_all_partial_trailer() {
+        let mut bytes = encode(&users().0[0]).unwrap();
+        bytes.extend(&encode(&users().0[1]).unwrap()[..3]);
+        let mut decoded = decode_all::<User>(&bytes);
+        assert_eq!(decoded.next().unwrap().unwrap(), users().0[0]);
+        assert!(matches!(decoded.next(), Some(Err(DecodeError::UnexpectedEnd { .. }))));
+        assert!(decoded.next().is_none());
+    }
+
+    #[test]
+    fn test_stream_round_trip() {
+        let mut cursor = Cursor::new(Vec::new());
+        let written = encode_into(&users(), &mut cursor).unwrap();
+        assert_eq!(written, cursor.get_ref().len());
+
+        cursor.set_position(0);
+        assert_eq!(decode_from::<Users, _>(&mut cursor).unwrap(), users());
+    }
+
+    #[test]
+    fn test_stream_round_trip_with_config() {
+        let config = config::standard().with_fixed_int_encoding().with_big_endian();
+        let mut cursor = Cursor::new(Vec::new());
+        encode_into_with_config(&users(), &mut cursor, config).unwrap();
+
+        cursor.set_position(0);
+        assert_eq!(decode_from_with_config::<Users, _, _>(&mut cursor, config).unwrap(), users());
+    }
+}
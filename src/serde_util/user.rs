@@ -0,0 +1,12 @@
+use bincode::{Decode, Encode};
+
+#[derive(Encode, Decode, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct User {
+    pub fname: String,
+    pub lname: String,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Users(pub Vec<User>);
@@ -0,0 +1,60 @@
+use std::borrow::Cow;
+
+/// Trims and lowercases `s`. A `&'static str` that only needs trimming (or
+/// nothing at all) comes back borrowed; an owned input with nothing to change
+/// is handed back without copying. Only real lowercasing allocates.
+pub fn normalize<T: Into<Cow<'static, str>>>(s: T) -> Cow<'static, str> {
+    let s = s.into();
+    if s.chars().any(changes_case) {
+        return Cow::Owned(s.trim().to_lowercase());
+    }
+    match s {
+        Cow::Borrowed(s) => Cow::Borrowed(s.trim()),
+        Cow::Owned(s) if s.trim().len() == s.len() => Cow::Owned(s),
+        Cow::Owned(s) => Cow::Owned(s.trim().to_string()),
+    }
+}
+
+fn changes_case(c: char) -> bool {
+    let mut lower = c.to_lowercase();
+    lower.next() != Some(c) || lower.next().is_some()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_normalize_unchanged_stays_borrowed() {
+        assert!(matches!(normalize("loony"), Cow::Borrowed("loony")));
+    }
+
+    #[test]
+    fn test_normalize_trim_only_stays_borrowed() {
+        assert!(matches!(normalize("  loony \n"), Cow::Borrowed("loony")));
+    }
+
+    #[test]
+    fn test_normalize_lowercases_into_owned() {
+        let normalized = normalize(" Loony ");
+        assert!(matches!(normalized, Cow::Owned(_)));
+        assert_eq!(normalized, "loony");
+    }
+
+    #[test]
+    fn test_normalize_owned_input_reuses_allocation() {
+        let s = String::from("loony");
+        let ptr = s.as_ptr();
+        match normalize(s) {
+            Cow::Owned(s) => assert_eq!(s.as_ptr(), ptr),
+            Cow::Borrowed(_) => panic!("expected the owned string back"),
+        }
+    }
+
+    #[test]
+    fn test_normalize_non_ascii() {
+        assert_eq!(normalize("ÉCOLE"), "école");
+        assert!(matches!(normalize("école"), Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,171 @@
+//! The parts of `unrust` that need nothing but `core`: the jump hash the
+//! gateway routes with, and the primitives behind the `Validate` derive's
+//! length and email checks. With default features off this crate is
+//! `no_std`; `alloc` adds `ValidateLen` impls for its collections and the
+//! `ValidationError` derived code reports with, and `std` adds the `HashMap`
+//! and `HashSet` impls. Build it without them to check it still compiles bare:
+//!
+//! ```text
+//! cargo build -p unrust_core --no-default-features
+//! ```
+
+#![no_std]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+#[cfg(feature = "alloc")]
+mod validation;
+
+#[cfg(feature = "alloc")]
+pub use validation::{__private, errors_by_field, FieldErrors, ValidationError};
+
+/// Lamping & Veach jump consistent hash. Maps `key` onto a bucket in
+/// `0..buckets` such that growing from N to N+1 buckets only moves ~1/(N+1)
+/// of the keys, all of them into the new bucket.
+///
+/// Never panics: `buckets <= 1`, including zero and negative counts, always
+/// maps to bucket 0.
+pub fn jump_hash(mut key: u64, buckets: i64) -> i64 {
+    if buckets <= 1 {
+        return 0;
+    }
+
+    let mut b: i64 = -1;
+    let mut j: i64 = 0;
+    while j < buckets {
+        b = j;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        j = ((b + 1) as f64 * ((1i64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    b
+}
+
+/// The length `min_length`, `max_length` and `non_empty` check. Implement it
+/// to use those attributes on your own types.
+pub trait ValidateLen {
+    fn validate_len(&self) -> usize;
+}
+
+/// Byte length, like `str::len`.
+impl ValidateLen for str {
+    fn validate_len(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T> ValidateLen for [T] {
+    fn validate_len(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T, const N: usize> ValidateLen for [T; N] {
+    fn validate_len(&self) -> usize {
+        N
+    }
+}
+
+impl<T: ValidateLen + ?Sized> ValidateLen for &T {
+    fn validate_len(&self) -> usize {
+        (**self).validate_len()
+    }
+}
+
+#[cfg(feature = "alloc")]
+mod alloc_impls {
+    use super::ValidateLen;
+    use alloc::collections::{BTreeMap, BTreeSet};
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    impl ValidateLen for String {
+        fn validate_len(&self) -> usize {
+            self.len()
+        }
+    }
+
+    impl<T> ValidateLen for Vec<T> {
+        fn validate_len(&self) -> usize {
+            self.len()
+        }
+    }
+
+    impl<K, V> ValidateLen for BTreeMap<K, V> {
+        fn validate_len(&self) -> usize {
+            self.len()
+        }
+    }
+
+    impl<T> ValidateLen for BTreeSet<T> {
+        fn validate_len(&self) -> usize {
+            self.len()
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+mod std_impls {
+    use super::ValidateLen;
+    use std::collections::{HashMap, HashSet};
+
+    impl<K, V, S> ValidateLen for HashMap<K, V, S> {
+        fn validate_len(&self) -> usize {
+            self.len()
+        }
+    }
+
+    impl<T, S> ValidateLen for HashSet<T, S> {
+        fn validate_len(&self) -> usize {
+            self.len()
+        }
+    }
+}
+
+/// The check behind `#[validate(email)]`: exactly one `@`, a non-empty local
+/// part, and a domain with a dot that neither starts nor ends it. No
+/// whitespace anywhere. Deliberately loose; it catches typos, not RFC 5322.
+pub fn is_email(value: &str) -> bool {
+    let Some((local, domain)) = value.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && !domain.contains('@')
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !value.chars().any(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_jump_hash_known_values() {
+        // fixed outputs, so a change to the arithmetic cannot go unnoticed
+        assert_eq!(jump_hash(0, 10), 0);
+        assert_eq!(jump_hash(1, 10), 6);
+        assert_eq!(jump_hash(0xdead_beef, 1000), 285);
+        assert_eq!(jump_hash(u64::MAX, 0), 0);
+    }
+
+    #[test]
+    fn test_validate_len_core_types() {
+        assert_eq!("héllo".validate_len(), 6);
+        assert_eq!([1, 2, 3][..].validate_len(), 3);
+        assert_eq!([0u8; 4].validate_len(), 4);
+        assert_eq!((&"abc").validate_len(), 3);
+    }
+
+    #[test]
+    fn test_is_email() {
+        assert!(is_email("ann@example.com"));
+        assert!(!is_email("ann@example"));
+        assert!(!is_email("ann@@example.com"));
+        assert!(!is_email("ann @example.com"));
+    }
+}
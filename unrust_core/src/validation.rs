@@ -0,0 +1,72 @@
+//! The runtime side of the `Validate` derive that needs only `alloc`. A
+//! `no_std` crate derives against this module's crate root with
+//! `#[validate(crate = "unrust_core")]`; `unrust::app_macro` re-exports the
+//! same types for everyone else.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+/// A single failed check reported by a derived `validate()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// The field that failed, e.g. `name`, `0` or `user.name` when nested.
+    /// Empty for struct-level `assert` rules; `fields_match` reports its `b`.
+    pub field: String,
+    /// Which check failed, e.g. `"min_length"` or `"range"`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub fn new(field: impl Into<String>, code: &'static str, message: impl Into<String>) -> Self {
+        Self { field: field.into(), code, message: message.into() }
+    }
+
+    /// Re-labels an error from a nested struct as `{parent}.{field}`.
+    pub fn nested(self, parent: &str) -> Self {
+        if self.field.is_empty() {
+            let message = format!("{parent}: {}", self.message);
+            return Self { field: parent.to_string(), code: self.code, message };
+        }
+        let field = format!("{parent}.{}", self.field);
+        let message = if self.message.starts_with(&self.field) {
+            format!("{parent}.{}", self.message)
+        } else {
+            format!("{field}: {}", self.message)
+        };
+        Self { field, code: self.code, message }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// What a derived `validate_map` returns through this crate.
+pub type FieldErrors = BTreeMap<String, Vec<String>>;
+
+/// Groups messages by `field`, keeping each field's messages in order.
+pub fn errors_by_field(errors: Vec<ValidationError>) -> FieldErrors {
+    let mut map = FieldErrors::new();
+    for error in errors {
+        map.entry(error.field).or_default().push(error.message);
+    }
+    map
+}
+
+/// What generated code names instead of the `std` prelude, so it compiles
+/// in `no_std` crates too. Not a stable API.
+#[doc(hidden)]
+pub mod __private {
+    pub use alloc::collections::BTreeSet;
+    pub use alloc::format;
+    pub use alloc::string::String;
+    pub use alloc::vec::Vec;
+    #[cfg(feature = "std")]
+    pub use std::sync::LazyLock;
+}
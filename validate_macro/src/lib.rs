@@ -0,0 +1,1137 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::meta::ParseNestedMeta;
+use syn::parse::{Parse, ParseStream};
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Expr, Fields, Ident, Index, Lit, LitInt, LitStr, Member, Path, Token, Type};
+
+/// Generates a `validate(&self) -> Result<(), Vec<ValidationError>>` method,
+/// plus `validate_map` returning the same messages keyed by field and
+/// `validate_into` converting the first into your own error type, from field
+/// attributes, either shorthand (`#[min_length = 3]`) or grouped
+/// (`#[validate(min_length = 3, max_length = 32)]`). Every failing check is
+/// reported, in field order. Tuple struct fields are reported by position.
+/// On an enum, the fields of whichever variant is present are checked, with
+/// the same attributes and labels as a struct's.
+/// Checks on an `Option<T>` field apply to the inner value; `None` passes.
+/// `rename = "userName"` reports a field under that name instead.
+/// `min_length`/`max_length` are meant for strings; bound a collection's
+/// size with `min_items`/`max_items`.
+/// `starts_with = ".."` and `ends_with = ".."` check a string's prefix and
+/// suffix. `each(..)` applies checks to every element of a `Vec`, and
+/// `each_key(..)` and `each_value(..)` to the entries of a map.
+///
+/// Rules spanning several fields go on the struct itself, as
+/// `#[validate(assert = "self.end >= self.start", message = "...")]` or
+/// `#[validate(fields_match(a = "password", b = "confirm_password"))]`;
+/// these run after the field checks. `#[validate(mode = "fail_fast")]`
+/// stops at the first failing check instead of reporting every one.
+///
+/// The generated code needs only `core` and `alloc`. It names its runtime
+/// support through `::unrust::app_macro`; a `no_std` crate depending on
+/// `unrust_core` with the `alloc` feature instead adds
+/// `#[validate(crate = "unrust_core")]`, and `validate_map` then returns a
+/// `BTreeMap`. `pattern` needs `unrust` and `std`; `unique` needs `Ord` items.
+#[proc_macro_derive(Validate, attributes(validate, min_length, max_length))]
+pub fn validate_derive(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let struct_name = &input.ident;
+
+    let mut mode = Mode::Collect;
+    let mut krate: Path = syn::parse_quote!(::unrust::app_macro);
+    let mut struct_checks = vec![];
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("validate")) {
+        struct_checks.extend(parse_struct_rule(attr, &mut mode, &mut krate)?);
+    }
+    // with fail_fast, every check is followed by a return on the first error
+    let stop = match mode {
+        Mode::Collect => quote!(),
+        Mode::FailFast => quote! {
+            if !errors.is_empty() {
+                errors.truncate(1);
+                return Err(errors);
+            }
+        },
+    };
+
+    let (mut field_checks, async_checks) = match &input.data {
+        Data::Struct(data_struct) => {
+            let (mut checks, mut async_checks) = (vec![], vec![]);
+            for field in field_checks(&data_struct.fields, |_, member| quote!(self.#member))? {
+                checks.extend(field.checks);
+                async_checks.extend(field.async_checks);
+            }
+            (checks, async_checks)
+        }
+        Data::Enum(data_enum) => enum_checks(data_enum)?,
+        Data::Union(data_union) => {
+            return Err(syn::Error::new_spanned(
+                data_union.union_token,
+                "Validate can only be derived for structs and enums, not unions",
+            ))
+        }
+    };
+    field_checks.extend(struct_checks);
+    let field_checks = with_stop(field_checks, &stop);
+    let async_checks = with_stop(async_checks, &stop);
+
+    let rt = runtime();
+    let (error, vec) = (quote!(#krate::ValidationError), quote!(#krate::__private::Vec));
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #struct_name #ty_generics #where_clause {
+            pub fn validate(&self) -> Result<(), #vec<#error>> {
+                #[allow(unused_imports)]
+                use #krate as #rt;
+                let mut errors: #vec<#error> = #vec::new();
+                #(#field_checks)*
+                if errors.is_empty() {
+                    Ok(())
+                } else {
+                    Err(errors)
+                }
+            }
+
+            /// Runs `validate`, then every `with_async` validator, and reports
+            /// all failures from both. `with_async` validators on nested
+            /// structs are not run.
+            pub async fn validate_async(&self) -> Result<(), #vec<#error>> {
+                #[allow(unused_imports)]
+                use #krate as #rt;
+                let mut errors: #vec<#error> = match self.validate() {
+                    Ok(()) => #vec::new(),
+                    Err(errors) => errors,
+                };
+                #stop
+                #(#async_checks)*
+                if errors.is_empty() {
+                    Ok(())
+                } else {
+                    Err(errors)
+                }
+            }
+
+            /// Like `validate`, but converts the first failure into the
+            /// caller's error type so it can be `?`-propagated.
+            pub fn validate_into<E: From<#error>>(&self) -> Result<(), E> {
+                match self.validate() {
+                    Ok(()) => Ok(()),
+                    Err(errors) => Err(E::from(errors.into_iter().next().expect("validate reports at least one error"))),
+                }
+            }
+
+            /// Like `validate`, with the messages grouped by field.
+            pub fn validate_map(&self) -> Result<(), #krate::FieldErrors> {
+                self.validate().map_err(#krate::errors_by_field)
+            }
+        }
+    })
+}
+
+/// Set by a struct-level `#[validate(mode = "..")]`.
+#[derive(Clone, Copy)]
+enum Mode {
+    /// Run every check and report all failures; the default.
+    Collect,
+    /// Stop at the first failing check and report only that.
+    FailFast,
+}
+
+/// The checks generated for one field, kept apart so an enum arm can bind
+/// only the fields that have some.
+struct FieldChecks {
+    index: usize,
+    member: Member,
+    checks: Vec<TokenStream2>,
+    async_checks: Vec<TokenStream2>,
+}
+
+/// The checks for each of `fields` that has any, reading the field through
+/// the place expression `place` builds from its position and member.
+fn field_checks(fields: &Fields, place: impl Fn(usize, &Member) -> TokenStream2) -> syn::Result<Vec<FieldChecks>> {
+    let mut all = vec![];
+    for (index, field) in fields.iter().enumerate() {
+        let (member, label) = match &field.ident {
+            Some(ident) => (Member::Named(ident.clone()), ident.to_string()),
+            None => (Member::Unnamed(Index::from(index)), index.to_string()),
+        };
+        let rules = FieldRules::parse(&field.attrs)?;
+        let label = rules.rename.as_ref().map_or(label, LitStr::value);
+        let label = quote!(#label);
+        if rules.skip {
+            continue;
+        }
+        let field_place = place(index, &member);
+        // checks on an `Option` apply to the inner value and skip `None`
+        let (checks, async_checks) = match option_inner(&field.ty) {
+            Some(inner) => {
+                let value = quote!((*value));
+                (
+                    if_some(&field_place, rules.checks(&value, &label, inner)).into_iter().collect(),
+                    if_some(&field_place, rules.async_checks(&value, &label)).into_iter().collect(),
+                )
+            }
+            None => (rules.checks(&field_place, &label, &field.ty), rules.async_checks(&field_place, &label)),
+        };
+        if !checks.is_empty() || !async_checks.is_empty() {
+            all.push(FieldChecks { index, member, checks, async_checks });
+        }
+    }
+    Ok(all)
+}
+
+/// A `match self` running the checks of whichever variant is present, one
+/// for `validate` and one for `validate_async`. Each arm binds only the
+/// fields it checks, under generated names so they cannot shadow `errors`.
+fn enum_checks(data_enum: &syn::DataEnum) -> syn::Result<(Vec<TokenStream2>, Vec<TokenStream2>)> {
+    let binding = |index: usize| quote::format_ident!("__field{}", index);
+    let (mut arms, mut async_arms) = (vec![], vec![]);
+    for variant in &data_enum.variants {
+        let name = &variant.ident;
+        let fields = field_checks(&variant.fields, |index, _| {
+            let binding = binding(index);
+            quote!((*#binding))
+        })?;
+        let arm = |pick: fn(&FieldChecks) -> &Vec<TokenStream2>| {
+            let used: Vec<&FieldChecks> = fields.iter().filter(|field| !pick(field).is_empty()).collect();
+            if used.is_empty() {
+                return None;
+            }
+            let pattern = match &variant.fields {
+                Fields::Named(_) => {
+                    let bound = used.iter().map(|field| {
+                        let (member, binding) = (&field.member, binding(field.index));
+                        quote!(#member: #binding)
+                    });
+                    quote!(Self::#name { #(#bound,)* .. })
+                }
+                Fields::Unnamed(unnamed) => {
+                    let bound = (0..unnamed.unnamed.len()).map(|index| {
+                        if used.iter().any(|field| field.index == index) {
+                            let binding = binding(index);
+                            quote!(#binding)
+                        } else {
+                            quote!(_)
+                        }
+                    });
+                    quote!(Self::#name(#(#bound),*))
+                }
+                Fields::Unit => unreachable!("a unit variant has no fields to check"),
+            };
+            let checks = used.iter().flat_map(|field| pick(field));
+            Some(quote!(#pattern => { #(#checks)* }))
+        };
+        arms.extend(arm(|field| &field.checks));
+        async_arms.extend(arm(|field| &field.async_checks));
+    }
+    let variants = data_enum.variants.len();
+    let matches = |arms: Vec<TokenStream2>| {
+        if arms.is_empty() {
+            return vec![];
+        }
+        // variants without checks fall through, if there are any
+        let rest = (arms.len() < variants).then(|| quote!(_ => {}));
+        vec![quote! {
+            match self {
+                #(#arms)*
+                #rest
+            }
+        }]
+    };
+    Ok((matches(arms), matches(async_arms)))
+}
+
+/// Follows every check with `stop`, which is empty unless `fail_fast`.
+fn with_stop(checks: Vec<TokenStream2>, stop: &TokenStream2) -> Vec<TokenStream2> {
+    checks.into_iter().map(|check| quote!(#check #stop)).collect()
+}
+
+/// The checks requested for a single field.
+#[derive(Default)]
+struct FieldRules {
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    /// Bounds on a collection's `.len()`, from `min_items` and `max_items`.
+    min_items: Option<usize>,
+    max_items: Option<usize>,
+    range_min: Option<TokenStream2>,
+    range_max: Option<TokenStream2>,
+    non_empty: bool,
+    email: bool,
+    /// Patterns already checked to parse, so the generated `expect` holds.
+    pattern: Vec<LitStr>,
+    starts_with: Vec<LitStr>,
+    ends_with: Vec<LitStr>,
+    /// The allowed values from `one_of = [..]`, if given.
+    one_of: Option<Vec<Lit>>,
+    /// `Some(case_insensitive)` from `unique` or `unique(case_insensitive)`.
+    unique: Option<bool>,
+    /// Length checks measure the trimmed value instead of the field.
+    trim: bool,
+    with: Vec<Path>,
+    /// Async validators, run only by `validate_async`.
+    with_async: Vec<Path>,
+    nested: bool,
+    /// Set by `#[validate(skip)]`; the field gets no checks at all.
+    skip: bool,
+    /// The name errors report the field under, instead of its Rust name.
+    rename: Option<LitStr>,
+    /// Rules from `each(..)`, applied to every element of a `Vec` or slice.
+    each: Option<Box<FieldRules>>,
+    /// Rules from `each_key(..)` and `each_value(..)`, for maps.
+    each_key: Option<Box<FieldRules>>,
+    each_value: Option<Box<FieldRules>>,
+}
+
+impl FieldRules {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut rules = Self::default();
+        // the attributes that last set each bound, to point at if they clash
+        let (mut min_attr, mut max_attr) = (None, None);
+        for attr in attrs {
+            let before = ((rules.min_length, rules.min_items), (rules.max_length, rules.max_items));
+            if attr.path().is_ident("validate") {
+                attr.parse_nested_meta(|meta| rules.parse_meta(meta))?;
+            } else if attr.path().is_ident("min_length") {
+                rules.min_length = Some(parse_shorthand_length(attr, "min_length")?);
+            } else if attr.path().is_ident("max_length") {
+                rules.max_length = Some(parse_shorthand_length(attr, "max_length")?);
+            }
+            if (rules.min_length, rules.min_items) != before.0 {
+                min_attr = Some(attr);
+            }
+            if (rules.max_length, rules.max_items) != before.1 {
+                max_attr = Some(attr);
+            }
+        }
+        if let (Err(message), Some(min_attr), Some(max_attr)) = (rules.check_lengths(), min_attr, max_attr) {
+            let mut error = syn::Error::new_spanned(min_attr, &message);
+            if !std::ptr::eq(min_attr, max_attr) {
+                error.combine(syn::Error::new_spanned(max_attr, &message));
+            }
+            return Err(error);
+        }
+        Ok(rules)
+    }
+
+    /// Rejects a `min_length` above the `max_length`, or a `min_items` above
+    /// the `max_items`, which no value could satisfy.
+    fn check_lengths(&self) -> Result<(), String> {
+        match ((self.min_length, self.max_length), (self.min_items, self.max_items)) {
+            ((Some(min), Some(max)), _) if min > max => Err(format!("min_length ({min}) must not exceed max_length ({max})")),
+            (_, (Some(min), Some(max))) if min > max => Err(format!("min_items ({min}) must not exceed max_items ({max})")),
+            _ => Ok(()),
+        }
+    }
+
+    fn parse_meta(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("min_length") {
+            self.min_length = Some(parse_length(meta.value()?, "min_length")?);
+        } else if meta.path.is_ident("max_length") {
+            self.max_length = Some(parse_length(meta.value()?, "max_length")?);
+        } else if meta.path.is_ident("min_items") {
+            self.min_items = Some(parse_length(meta.value()?, "min_items")?);
+        } else if meta.path.is_ident("max_items") {
+            self.max_items = Some(parse_length(meta.value()?, "max_items")?);
+        } else if meta.path.is_ident("with") {
+            self.with.push(parse_path(meta.value()?, "with")?);
+        } else if meta.path.is_ident("with_async") {
+            self.with_async.push(parse_path(meta.value()?, "with_async")?);
+        } else if meta.path.is_ident("nested") {
+            self.nested = true;
+        } else if meta.path.is_ident("non_empty") {
+            self.non_empty = true;
+        } else if meta.path.is_ident("pattern") {
+            let lit: LitStr = meta.value()?.parse()?;
+            if let Err(e) = validate_pattern::Pattern::new(&lit.value()) {
+                return Err(syn::Error::new_spanned(&lit, format!("invalid pattern: {e}")));
+            }
+            self.pattern.push(lit);
+        } else if meta.path.is_ident("starts_with") {
+            self.starts_with.push(meta.value()?.parse()?);
+        } else if meta.path.is_ident("ends_with") {
+            self.ends_with.push(meta.value()?.parse()?);
+        } else if meta.path.is_ident("each") {
+            self.each = Some(parse_element_rules(&meta, "each")?);
+        } else if meta.path.is_ident("each_key") {
+            self.each_key = Some(parse_element_rules(&meta, "each_key")?);
+        } else if meta.path.is_ident("each_value") {
+            self.each_value = Some(parse_element_rules(&meta, "each_value")?);
+        } else if meta.path.is_ident("unique") {
+            let mut case_insensitive = false;
+            if meta.input.peek(syn::token::Paren) {
+                meta.parse_nested_meta(|option| {
+                    if option.path.is_ident("case_insensitive") {
+                        case_insensitive = true;
+                        Ok(())
+                    } else {
+                        Err(option.error("unknown unique option, expected `case_insensitive`"))
+                    }
+                })?;
+            }
+            self.unique = Some(case_insensitive);
+        } else if meta.path.is_ident("one_of") {
+            self.one_of = Some(parse_one_of(meta.value()?)?);
+        } else if meta.path.is_ident("trim") {
+            self.trim = true;
+        } else if meta.path.is_ident("skip") {
+            self.skip = true;
+        } else if meta.path.is_ident("rename") {
+            let lit: LitStr = meta.value()?.parse()?;
+            if lit.value().is_empty() {
+                return Err(syn::Error::new_spanned(&lit, "rename expects a non-empty name"));
+            }
+            self.rename = Some(lit);
+        } else if meta.path.is_ident("email") {
+            self.email = true;
+        } else if meta.path.is_ident("range") {
+            meta.parse_nested_meta(|bound| {
+                if bound.path.is_ident("min") {
+                    self.range_min = Some(parse_int(bound.value()?)?);
+                } else if bound.path.is_ident("max") {
+                    self.range_max = Some(parse_int(bound.value()?)?);
+                } else {
+                    return Err(bound.error("unknown range bound, expected `min` or `max`"));
+                }
+                Ok(())
+            })?;
+        } else {
+            let path = &meta.path;
+            return Err(meta.error(format!("unknown validate attribute `{}`", quote!(#path))));
+        }
+        Ok(())
+    }
+
+    fn checks(&self, value: &TokenStream2, label: &TokenStream2, ty: &Type) -> Vec<TokenStream2> {
+        let (rt, error) = (runtime(), error_path());
+        let format = quote!(#rt::__private::format);
+        let len = if self.trim {
+            quote!(#rt::ValidateLen::validate_len(#value.trim()))
+        } else {
+            quote!(#rt::ValidateLen::validate_len(&#value))
+        };
+        let mut checks = vec![];
+        if self.non_empty {
+            checks.push(quote! {
+                if #len == 0 {
+                    errors.push(#error::new(#label, "non_empty", #format!("{} must not be empty", #label)));
+                }
+            });
+        }
+        if let Some(min_length) = self.min_length {
+            checks.push(quote! {
+                if #len < #min_length {
+                    errors.push(#error::new(#label, "min_length", #format!("{} must be at least {} characters long", #label, #min_length)));
+                }
+            });
+        }
+        if let Some(max_length) = self.max_length {
+            checks.push(quote! {
+                if #len > #max_length {
+                    errors.push(#error::new(#label, "max_length", #format!("{} must be at most {} characters long", #label, #max_length)));
+                }
+            });
+        }
+        if let Some(min_items) = self.min_items {
+            checks.push(quote! {
+                if #value.len() < #min_items {
+                    errors.push(#error::new(#label, "min_items", #format!("{} must contain at least {} items", #label, #min_items)));
+                }
+            });
+        }
+        if let Some(max_items) = self.max_items {
+            checks.push(quote! {
+                if #value.len() > #max_items {
+                    errors.push(#error::new(#label, "max_items", #format!("{} must contain at most {} items", #label, #max_items)));
+                }
+            });
+        }
+        for prefix in &self.starts_with {
+            checks.push(quote! {
+                if !#value.starts_with(#prefix) {
+                    errors.push(#error::new(#label, "starts_with", #format!("{} must start with {:?}", #label, #prefix)));
+                }
+            });
+        }
+        for suffix in &self.ends_with {
+            checks.push(quote! {
+                if !#value.ends_with(#suffix) {
+                    errors.push(#error::new(#label, "ends_with", #format!("{} must end with {:?}", #label, #suffix)));
+                }
+            });
+        }
+        if self.email {
+            checks.push(quote! {
+                if !#rt::is_email(&#value) {
+                    errors.push(#error::new(#label, "email", #format!("{} must be a valid email address", #label)));
+                }
+            });
+        }
+        if let Some(case_insensitive) = self.unique {
+            let key = if case_insensitive {
+                quote!(::core::convert::AsRef::<str>::as_ref(item).to_lowercase())
+            } else {
+                quote!(*item)
+            };
+            checks.push(quote! {
+                {
+                    let mut seen = #rt::__private::BTreeSet::new();
+                    if let Some(item) = #value.iter().find(|item| !seen.insert(#key)) {
+                        errors.push(#error::new(#label, "unique", #format!("{} must not contain duplicates, found {:?}", #label, item)));
+                    }
+                }
+            });
+        }
+        for pattern in &self.pattern {
+            checks.push(quote! {
+                {
+                    static PATTERN: #rt::__private::LazyLock<#rt::Pattern> =
+                        #rt::__private::LazyLock::new(|| #rt::Pattern::new(#pattern).expect("checked by the derive"));
+                    if !PATTERN.is_match(&#value) {
+                        errors.push(#error::new(#label, "pattern", #format!("{} must match the pattern {}", #label, #pattern)));
+                    }
+                }
+            });
+        }
+        if let Some(values) = &self.one_of {
+            let allowed = values.iter().map(|value| quote!(#value).to_string()).collect::<Vec<_>>().join(", ");
+            checks.push(quote! {
+                if #(#value != #values)&&* {
+                    errors.push(#error::new(#label, "one_of", #format!("{} must be one of [{}]", #label, #allowed)));
+                }
+            });
+        }
+        if let Some(min) = &self.range_min {
+            checks.push(quote! {
+                if #value < #min {
+                    errors.push(#error::new(#label, "range", #format!("{} must be at least {}", #label, #min)));
+                }
+            });
+        }
+        if let Some(max) = &self.range_max {
+            checks.push(quote! {
+                if #value > #max {
+                    errors.push(#error::new(#label, "range", #format!("{} must be at most {}", #label, #max)));
+                }
+            });
+        }
+        for with in &self.with {
+            checks.push(quote! {
+                if let Err(e) = #with(&#value) {
+                    errors.push(#error::new(#label, "with", e));
+                }
+            });
+        }
+        if self.nested && is_vec(ty) {
+            checks.push(quote! {
+                for (index, item) in #value.iter().enumerate() {
+                    if let Err(inner) = item.validate() {
+                        errors.extend(inner.into_iter().map(|e| e.nested(&#format!("{}[{}]", #label, index))));
+                    }
+                }
+            });
+        } else if self.nested {
+            checks.push(quote! {
+                if let Err(inner) = #value.validate() {
+                    errors.extend(inner.into_iter().map(|e| e.nested(#label)));
+                }
+            });
+        }
+        if let Some(each) = &self.each {
+            let item = quote!((*item));
+            let item_checks = each.checks(&item, &quote!(&label), &generic_arg(ty, 0));
+            checks.push(quote! {
+                for (index, item) in #value.iter().enumerate() {
+                    let label = #format!("{}[{}]", #label, index);
+                    #(#item_checks)*
+                }
+            });
+        }
+        if self.each_key.is_some() || self.each_value.is_some() {
+            let key_checks = self
+                .each_key
+                .as_ref()
+                .map_or(vec![], |rules| rules.checks(&quote!((*key)), &quote!(&label), &generic_arg(ty, 0)));
+            let value_checks = self
+                .each_value
+                .as_ref()
+                .map_or(vec![], |rules| rules.checks(&quote!((*item)), &quote!(&label), &generic_arg(ty, 1)));
+            checks.push(quote! {
+                for (key, item) in #value.iter() {
+                    {
+                        let label = #format!("{} key {:?}", #label, key);
+                        #(#key_checks)*
+                    }
+                    {
+                        let label = #format!("{}[{:?}]", #label, key);
+                        #(#value_checks)*
+                    }
+                }
+            });
+        }
+        checks
+    }
+}
+
+/// Parses the parenthesized rules of `each(..)`, `each_key(..)` or
+/// `each_value(..)`, which take the same checks as a field.
+fn parse_element_rules(meta: &ParseNestedMeta, name: &str) -> syn::Result<Box<FieldRules>> {
+    let mut rules = FieldRules::default();
+    meta.parse_nested_meta(|inner| rules.parse_meta(inner))?;
+    if !rules.with_async.is_empty() || rules.skip || rules.rename.is_some() {
+        return Err(meta.error(format!("{name} does not support `with_async`, `skip` or `rename`")));
+    }
+    rules.check_lengths().map_err(|message| meta.error(message))?;
+    Ok(Box::new(rules))
+}
+
+/// The `n`th generic argument of a type such as `Vec<T>` or `HashMap<K, V>`,
+/// or `()` if it has none, for deciding how element rules expand.
+fn generic_arg(ty: &Type, n: usize) -> Type {
+    if let Type::Path(type_path) = ty {
+        if let Some(syn::PathArguments::AngleBracketed(args)) = type_path.path.segments.last().map(|segment| &segment.arguments) {
+            if let Some(syn::GenericArgument::Type(arg)) = args.args.iter().nth(n) {
+                return arg.clone();
+            }
+        }
+    }
+    syn::parse_quote!(())
+}
+
+/// Parses a struct-level `#[validate(assert = "expr", message = "...")]` or
+/// `#[validate(fields_match(a = "..", b = ".."))]` into its check. Without a
+/// `message` the expression, or the two field names, are reported. A
+/// `mode = ".."` is stored in `mode` and a `crate = ".."` in `krate`; an
+/// attribute with only those has no check.
+fn parse_struct_rule(attr: &Attribute, mode: &mut Mode, krate: &mut Path) -> syn::Result<Option<TokenStream2>> {
+    let mut assert: Option<(Expr, String)> = None;
+    let mut fields_match: Option<(Ident, Ident)> = None;
+    let mut message: Option<String> = None;
+    let mut has_option = false;
+    attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("crate") {
+            let lit: LitStr = meta.value()?.parse()?;
+            *krate = lit.parse().map_err(|_| syn::Error::new_spanned(&lit, "crate expects a path such as \"unrust_core\""))?;
+            has_option = true;
+        } else if meta.path.is_ident("mode") {
+            let lit: LitStr = meta.value()?.parse()?;
+            *mode = match lit.value().as_str() {
+                "collect" => Mode::Collect,
+                "fail_fast" => Mode::FailFast,
+                _ => return Err(syn::Error::new_spanned(&lit, "unknown mode, expected `collect` or `fail_fast`")),
+            };
+            has_option = true;
+        } else if meta.path.is_ident("assert") {
+            let lit: LitStr = meta.value()?.parse()?;
+            let expr = syn::parse_str::<Expr>(&lit.value())
+                .map_err(|_| syn::Error::new_spanned(&lit, "assert expects an expression such as \"self.end >= self.start\""))?;
+            assert = Some((expr, lit.value()));
+        } else if meta.path.is_ident("fields_match") {
+            fields_match = Some(parse_fields_match(&meta)?);
+        } else if meta.path.is_ident("message") {
+            let lit: LitStr = meta.value()?.parse()?;
+            message = Some(lit.value());
+        } else {
+            let path = &meta.path;
+            return Err(meta.error(format!("unknown struct-level validate attribute `{}`", quote!(#path))));
+        }
+        Ok(())
+    })?;
+
+    let error = error_path();
+    match (assert, fields_match) {
+        (Some((expr, source)), None) => {
+            let message = message.unwrap_or_else(|| format!("assertion failed: {source}"));
+            Ok(Some(quote! {
+                if !(#expr) {
+                    errors.push(#error::new("", "assert", #message));
+                }
+            }))
+        }
+        // reported against `b`, the field a form would show the error on
+        (None, Some((a, b))) => {
+            let message = message.unwrap_or_else(|| format!("{a} and {b} must match"));
+            let label = b.to_string();
+            Ok(Some(quote! {
+                if self.#a != self.#b {
+                    errors.push(#error::new(#label, "fields_match", #message));
+                }
+            }))
+        }
+        (Some(_), Some(_)) => {
+            Err(syn::Error::new_spanned(attr, "use `assert` and `fields_match` in separate `#[validate(..)]` attributes"))
+        }
+        (None, None) if has_option && message.is_none() => Ok(None),
+        (None, None) => Err(syn::Error::new_spanned(
+            attr,
+            "struct-level validate expects `assert = \"expr\"` or `fields_match(a = \"..\", b = \"..\")`",
+        )),
+    }
+}
+
+/// Parses the `(a = "password", b = "confirm_password")` of `fields_match`
+/// into the two field names.
+fn parse_fields_match(meta: &ParseNestedMeta) -> syn::Result<(Ident, Ident)> {
+    let (mut a, mut b) = (None, None);
+    meta.parse_nested_meta(|inner| {
+        let slot = if inner.path.is_ident("a") {
+            &mut a
+        } else if inner.path.is_ident("b") {
+            &mut b
+        } else {
+            return Err(inner.error("unknown fields_match option, expected `a` or `b`"));
+        };
+        let lit: LitStr = inner.value()?.parse()?;
+        let ident = lit.parse::<Ident>().map_err(|_| syn::Error::new_spanned(&lit, "fields_match expects a field name"))?;
+        *slot = Some(ident);
+        Ok(())
+    })?;
+    match (a, b) {
+        (Some(a), Some(b)) => Ok((a, b)),
+        _ => Err(meta.error("fields_match expects both `a = \"field\"` and `b = \"field\"`")),
+    }
+}
+
+impl FieldRules {
+    fn async_checks(&self, value: &TokenStream2, label: &TokenStream2) -> Vec<TokenStream2> {
+        let error = error_path();
+        self.with_async
+            .iter()
+            .map(|with| {
+                quote! {
+                    if let Err(e) = #with(&#value).await {
+                        errors.push(#error::new(#label, "with_async", e));
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// The alias generated method bodies import the runtime support under, from
+/// `::unrust::app_macro` or the struct's `#[validate(crate = "..")]`. Checks
+/// name everything through it, `Vec` and `format!` included, so the output
+/// compiles in `no_std` crates.
+fn runtime() -> TokenStream2 {
+    quote!(__validate)
+}
+
+/// The runtime error type generated code reports failures with.
+fn error_path() -> TokenStream2 {
+    let rt = runtime();
+    quote!(#rt::ValidationError)
+}
+
+/// Wraps `checks` so they only run, against `value`, when the field read
+/// through `place` is `Some`.
+fn if_some(place: &TokenStream2, checks: Vec<TokenStream2>) -> Option<TokenStream2> {
+    if checks.is_empty() {
+        return None;
+    }
+    Some(quote! {
+        if let Some(value) = &#place {
+            #(#checks)*
+        }
+    })
+}
+
+/// The `T` of an `Option<T>` field type.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(type_path) = ty else {
+        return None;
+    };
+    let segment = type_path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            syn::GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_vec(ty: &Type) -> bool {
+    match ty {
+        Type::Path(type_path) => type_path.path.segments.last().is_some_and(|segment| segment.ident == "Vec"),
+        _ => false,
+    }
+}
+
+/// Parses the `= N` of a `name = N` length bound.
+fn parse_length(input: ParseStream, name: &str) -> syn::Result<usize> {
+    let lit: Lit = input.parse()?;
+    lit_to_length(&lit, name)
+}
+
+/// Parses a shorthand `#[name = N]` field attribute.
+fn parse_shorthand_length(attr: &Attribute, name: &str) -> syn::Result<usize> {
+    if let syn::Meta::NameValue(nv) = &attr.meta {
+        if let syn::Expr::Lit(lit) = &nv.value {
+            return lit_to_length(&lit.lit, name);
+        }
+        return Err(syn::Error::new_spanned(&nv.value, format!("{name} expects an integer literal")));
+    }
+    Err(syn::Error::new_spanned(attr, format!("expected `#[{name} = N]`")))
+}
+
+fn lit_to_length(lit: &Lit, name: &str) -> syn::Result<usize> {
+    match lit {
+        Lit::Int(len) => len.base10_parse(),
+        _ => Err(syn::Error::new_spanned(lit, format!("{name} expects an integer literal"))),
+    }
+}
+
+/// Parses the `= "path::to::item"` of a `name = "..."` attribute.
+fn parse_path(input: ParseStream, name: &str) -> syn::Result<Path> {
+    let lit: LitStr = input.parse()?;
+    syn::parse_str(&lit.value())
+        .map_err(|_| syn::Error::new_spanned(&lit, format!("{name} expects a path such as \"path::to::fn\"")))
+}
+
+/// Parses the `= ["a", "b"]` of `one_of`: a bracketed, comma-separated list
+/// of literals the field is compared against with `==`.
+fn parse_one_of(input: ParseStream) -> syn::Result<Vec<Lit>> {
+    let content;
+    let brackets = syn::bracketed!(content in input);
+    let values: Vec<Lit> = content.parse_terminated(Lit::parse, Token![,])?.into_iter().collect();
+    if values.is_empty() {
+        return Err(syn::Error::new(brackets.span.join(), "one_of expects at least one value"));
+    }
+    Ok(values)
+}
+
+/// Parses an optionally negated integer literal, keeping it unsuffixed so it
+/// takes on the type of the field it is compared against.
+fn parse_int(input: ParseStream) -> syn::Result<TokenStream2> {
+    let neg: Option<Token![-]> = input.parse()?;
+    let lit: LitInt = input.parse()?;
+    lit.base10_parse::<i128>()?;
+    Ok(quote! { #neg #lit })
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use syn::parse_quote;
+
+    fn expand_err(input: DeriveInput) -> String {
+        expand(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_shorthand_expects_integer() {
+        let err = expand_err(parse_quote! {
+            struct User {
+                #[min_length = "x"]
+                name: String,
+            }
+        });
+        assert_eq!(err, "min_length expects an integer literal");
+    }
+
+    #[test]
+    fn test_min_length_above_max_length() {
+        let err = expand_err(parse_quote! {
+            struct User {
+                #[validate(min_length = 10, max_length = 5)]
+                name: String,
+            }
+        });
+        assert_eq!(err, "min_length (10) must not exceed max_length (5)");
+
+        // split across attributes, both are pointed at
+        let err = expand(parse_quote! {
+            struct User {
+                #[min_length = 10]
+                #[max_length = 5]
+                name: String,
+            }
+        })
+        .unwrap_err();
+        assert_eq!(err.into_iter().count(), 2);
+
+        let err = expand_err(parse_quote! {
+            struct Team {
+                #[validate(each(min_length = 3, max_length = 2))]
+                members: Vec<String>,
+            }
+        });
+        assert_eq!(err, "min_length (3) must not exceed max_length (2)");
+    }
+
+    #[test]
+    fn test_min_items_above_max_items() {
+        let err = expand_err(parse_quote! {
+            struct Team {
+                #[validate(min_items = 3, max_items = 1)]
+                members: Vec<String>,
+            }
+        });
+        assert_eq!(err, "min_items (3) must not exceed max_items (1)");
+    }
+
+    #[test]
+    fn test_equal_length_bounds_accepted() {
+        assert!(expand(parse_quote! {
+            struct Pin {
+                #[validate(min_length = 4, max_length = 4)]
+                code: String,
+            }
+        })
+        .is_ok());
+    }
+
+    #[test]
+    fn test_rename_expects_name() {
+        let err = expand_err(parse_quote! {
+            struct User {
+                #[validate(rename = "", min_length = 3)]
+                user_name: String,
+            }
+        });
+        assert_eq!(err, "rename expects a non-empty name");
+    }
+
+    #[test]
+    fn test_grouped_expects_integer() {
+        let err = expand_err(parse_quote! {
+            struct User {
+                #[validate(max_length = "x")]
+                name: String,
+            }
+        });
+        assert_eq!(err, "max_length expects an integer literal");
+    }
+
+    #[test]
+    fn test_unknown_attribute() {
+        let err = expand_err(parse_quote! {
+            struct User {
+                #[validate(colour = 3)]
+                name: String,
+            }
+        });
+        assert_eq!(err, "unknown validate attribute `colour`");
+    }
+
+    #[test]
+    fn test_unknown_range_bound() {
+        let err = expand_err(parse_quote! {
+            struct Order {
+                #[validate(range(low = 1))]
+                quantity: u32,
+            }
+        });
+        assert_eq!(err, "unknown range bound, expected `min` or `max`");
+    }
+
+    #[test]
+    fn test_with_expects_path() {
+        let err = expand_err(parse_quote! {
+            struct Host {
+                #[validate(with = "not a path")]
+                name: String,
+            }
+        });
+        assert_eq!(err, "with expects a path such as \"path::to::fn\"");
+    }
+
+    #[test]
+    fn test_accepts_tuple_struct() {
+        assert!(expand(parse_quote! {
+            struct Wrapper(#[min_length = 3] String);
+        })
+        .is_ok());
+    }
+
+    #[test]
+    fn test_skip_emits_no_checks() {
+        let skipped = expand(parse_quote! {
+            struct User {
+                #[validate(skip, min_length = 3)]
+                name: String,
+            }
+        })
+        .unwrap();
+        let empty = expand(parse_quote! {
+            struct User {
+                name: String,
+            }
+        })
+        .unwrap();
+        assert_eq!(skipped.to_string(), empty.to_string());
+    }
+
+    #[test]
+    fn test_keeps_generics() {
+        let tokens = expand(parse_quote! {
+            struct Wrapper<T: Clone> where T: Default {
+                #[min_length = 3]
+                name: String,
+                value: T,
+            }
+        })
+        .unwrap()
+        .to_string();
+        assert!(tokens.starts_with("impl < T : Clone > Wrapper < T > where T : Default"), "{tokens}");
+    }
+
+    #[test]
+    fn test_assert_expects_expression() {
+        let err = expand_err(parse_quote! {
+            #[validate(assert = "self.end >=")]
+            struct Range {
+                start: u32,
+                end: u32,
+            }
+        });
+        assert_eq!(err, "assert expects an expression such as \"self.end >= self.start\"");
+    }
+
+    #[test]
+    fn test_fields_match_expects_both_fields() {
+        let err = expand_err(parse_quote! {
+            #[validate(fields_match(a = "password"))]
+            struct Signup {
+                password: String,
+                confirm_password: String,
+            }
+        });
+        assert_eq!(err, "fields_match expects both `a = \"field\"` and `b = \"field\"`");
+    }
+
+    #[test]
+    fn test_unknown_mode() {
+        let err = expand_err(parse_quote! {
+            #[validate(mode = "lazy")]
+            struct User {
+                name: String,
+            }
+        });
+        assert_eq!(err, "unknown mode, expected `collect` or `fail_fast`");
+    }
+
+    #[test]
+    fn test_invalid_crate_path() {
+        let err = expand_err(parse_quote! {
+            #[validate(crate = "unrust core")]
+            struct User {
+                name: String,
+            }
+        });
+        assert_eq!(err, "crate expects a path such as \"unrust_core\"");
+    }
+
+    #[test]
+    fn test_message_without_assert() {
+        let err = expand_err(parse_quote! {
+            #[validate(message = "end must not be before start")]
+            struct Range {
+                start: u32,
+                end: u32,
+            }
+        });
+        assert_eq!(err, "struct-level validate expects `assert = \"expr\"` or `fields_match(a = \"..\", b = \"..\")`");
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let err = expand_err(parse_quote! {
+            struct Page {
+                #[validate(pattern = "^[a-z")]
+                slug: String,
+            }
+        });
+        assert_eq!(err, "invalid pattern: unclosed character class");
+    }
+
+    #[test]
+    fn test_unique_unknown_option() {
+        let err = expand_err(parse_quote! {
+            struct Team {
+                #[validate(unique(ignore_case))]
+                members: Vec<String>,
+            }
+        });
+        assert_eq!(err, "unknown unique option, expected `case_insensitive`");
+    }
+
+    #[test]
+    fn test_each_rejects_async() {
+        let err = expand_err(parse_quote! {
+            struct Team {
+                #[validate(each(with_async = "check"))]
+                members: Vec<String>,
+            }
+        });
+        assert_eq!(err, "each does not support `with_async`, `skip` or `rename`");
+    }
+
+    #[test]
+    fn test_one_of_expects_values() {
+        let err = expand_err(parse_quote! {
+            struct Ticket {
+                #[validate(one_of = [])]
+                status: String,
+            }
+        });
+        assert_eq!(err, "one_of expects at least one value");
+    }
+
+    #[test]
+    fn test_one_of_expects_list() {
+        let err = expand_err(parse_quote! {
+            struct Ticket {
+                #[validate(one_of = "open")]
+                status: String,
+            }
+        });
+        assert_eq!(err, "expected square brackets");
+    }
+
+    #[test]
+    fn test_with_async_expects_path() {
+        let err = expand_err(parse_quote! {
+            struct Signup {
+                #[validate(with_async = "not a path")]
+                username: String,
+            }
+        });
+        assert_eq!(err, "with_async expects a path such as \"path::to::fn\"");
+    }
+
+    #[test]
+    fn test_rejects_union() {
+        let err = expand_err(parse_quote! {
+            union Bits {
+                int: u32,
+                float: f32,
+            }
+        });
+        assert_eq!(err, "Validate can only be derived for structs and enums, not unions");
+    }
+
+    #[test]
+    fn test_enum_binds_only_checked_fields() {
+        let tokens = expand(parse_quote! {
+            enum Payload {
+                Text(u8, #[min_length = 1] String),
+                Batch { #[validate(min_items = 1)] items: Vec<u8>, tag: u8 },
+                Empty,
+            }
+        })
+        .unwrap()
+        .to_string();
+        assert!(tokens.contains("Self :: Text (_ , __field1)"), "{tokens}");
+        assert!(tokens.contains("Self :: Batch { items : __field0 , .. }"), "{tokens}");
+        assert!(tokens.contains("_ => { }"), "{tokens}");
+    }
+}
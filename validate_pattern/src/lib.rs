@@ -0,0 +1,534 @@
+//! A small pattern matcher for `#[validate(pattern = "...")]`.
+//!
+//! Supports the regex syntax validation rules tend to need: literals, `.`,
+//! classes (`[a-z0-9-]`, `[^@]`), `\d \w \s` and their negations, groups with
+//! `|`, the quantifiers `* + ? {n} {n,} {n,m}`, and the anchors `^` and `$`.
+//! Like `Regex::is_match`, an unanchored pattern may match anywhere in the
+//! input. Backreferences, lookaround and lazy quantifiers are not supported.
+//!
+//! Patterns compile to a Thompson NFA that is simulated one input character
+//! at a time (a Pike VM without captures), so matching takes time linear in
+//! the input and uses no recursion, whatever the pattern.
+
+use std::fmt;
+
+/// A parsed pattern, ready to match.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    program: Vec<Inst>,
+}
+
+/// Why a pattern failed to parse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternError {
+    message: String,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Literal(char),
+    Any,
+    Class(Class),
+    Group(Vec<Vec<Node>>),
+    Repeat { node: Box<Node>, min: usize, max: Option<usize> },
+    Start,
+    End,
+}
+
+/// An NFA state. `Split` and `Jmp` hold the indices of the states they lead to.
+#[derive(Debug, Clone)]
+enum Inst {
+    Literal(char),
+    Any,
+    Class(Class),
+    Split(usize, usize),
+    Jmp(usize),
+    Start,
+    End,
+    Match,
+}
+
+/// Patterns that compile to more states than this are rejected, since
+/// counted repetition like `(a{1000}){1000}` multiplies out.
+const MAX_PROGRAM_LEN: usize = 100_000;
+
+#[derive(Debug, Clone)]
+struct Class {
+    negated: bool,
+    ranges: Vec<(char, char)>,
+}
+
+impl Class {
+    fn matches(&self, c: char) -> bool {
+        self.ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != self.negated
+    }
+}
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+const SPACE: &[(char, char)] = &[('\t', '\r'), (' ', ' ')];
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Self, PatternError> {
+        let mut parser = Parser { chars: pattern.chars().collect(), pos: 0 };
+        let alternatives = parser.alternatives()?;
+        if parser.pos < parser.chars.len() {
+            return Err(error("unmatched `)`"));
+        }
+        let mut compiler = Compiler { program: vec![] };
+        compiler.alternatives(&alternatives)?;
+        compiler.emit(Inst::Match)?;
+        Ok(Self { program: compiler.program })
+    }
+
+    /// Whether the pattern matches anywhere in `input`.
+    pub fn is_match(&self, input: &str) -> bool {
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut stack = vec![];
+        let mut chars = input.chars();
+        let mut at_start = true;
+        loop {
+            let c = chars.next();
+            // a fresh thread at every position makes the pattern unanchored
+            if self.add(&mut current, &mut stack, 0, at_start, c.is_none()) {
+                return true;
+            }
+            let Some(c) = c else {
+                return false;
+            };
+            next.clear();
+            let at_end = chars.as_str().is_empty();
+            for &pc in &current.pcs {
+                let advances = match &self.program[pc] {
+                    Inst::Literal(l) => *l == c,
+                    Inst::Any => true,
+                    Inst::Class(class) => class.matches(c),
+                    _ => false,
+                };
+                if advances && self.add(&mut next, &mut stack, pc + 1, false, at_end) {
+                    return true;
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            at_start = false;
+        }
+    }
+
+    /// Adds the thread at `pc` to `threads`, following `Split`s, `Jmp`s and
+    /// satisfied anchors, and returns whether it reaches `Match`.
+    fn add(&self, threads: &mut Threads, stack: &mut Vec<usize>, pc: usize, at_start: bool, at_end: bool) -> bool {
+        stack.push(pc);
+        while let Some(pc) = stack.pop() {
+            if !threads.insert(pc) {
+                continue;
+            }
+            match self.program[pc] {
+                Inst::Jmp(to) => stack.push(to),
+                Inst::Split(first, second) => stack.extend([second, first]),
+                Inst::Start if at_start => stack.push(pc + 1),
+                Inst::End if at_end => stack.push(pc + 1),
+                Inst::Match => {
+                    stack.clear();
+                    return true;
+                }
+                _ => {}
+            }
+        }
+        false
+    }
+}
+
+/// The set of NFA states live at one input position, in insertion order.
+struct Threads {
+    pcs: Vec<usize>,
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(len: usize) -> Self {
+        Self { pcs: vec![], seen: vec![false; len] }
+    }
+
+    fn insert(&mut self, pc: usize) -> bool {
+        if self.seen[pc] {
+            return false;
+        }
+        self.seen[pc] = true;
+        self.pcs.push(pc);
+        true
+    }
+
+    fn clear(&mut self) {
+        for pc in self.pcs.drain(..) {
+            self.seen[pc] = false;
+        }
+    }
+}
+
+fn error(message: &str) -> PatternError {
+    PatternError { message: message.to_string() }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    /// `seq ('|' seq)*`, stopping before a `)` or the end of input.
+    fn alternatives(&mut self) -> Result<Vec<Vec<Node>>, PatternError> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alternatives.push(self.sequence()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn sequence(&mut self) -> Result<Vec<Node>, PatternError> {
+        let mut nodes = vec![];
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantified(atom)?);
+        }
+        Ok(nodes)
+    }
+
+    fn atom(&mut self) -> Result<Node, PatternError> {
+        match self.next() {
+            Some('(') => {
+                if self.chars[self.pos..].starts_with(&['?', ':']) {
+                    self.pos += 2;
+                }
+                let alternatives = self.alternatives()?;
+                if self.next() != Some(')') {
+                    return Err(error("unclosed group"));
+                }
+                Ok(Node::Group(alternatives))
+            }
+            Some('[') => self.class(),
+            Some('.') => Ok(Node::Any),
+            Some('^') => Ok(Node::Start),
+            Some('$') => Ok(Node::End),
+            Some('\\') => self.escape(),
+            Some('*' | '+' | '?' | '{') => Err(error("nothing to repeat")),
+            Some(c) => Ok(Node::Literal(c)),
+            None => unreachable!("sequence checks for end of input"),
+        }
+    }
+
+    fn escape(&mut self) -> Result<Node, PatternError> {
+        let class = |ranges: &[(char, char)], negated| Ok(Node::Class(Class { negated, ranges: ranges.to_vec() }));
+        match self.next() {
+            Some('d') => class(DIGIT, false),
+            Some('D') => class(DIGIT, true),
+            Some('w') => class(WORD, false),
+            Some('W') => class(WORD, true),
+            Some('s') => class(SPACE, false),
+            Some('S') => class(SPACE, true),
+            Some(c) => self.escaped_char(c).map(Node::Literal),
+            None => Err(error("trailing backslash")),
+        }
+    }
+
+    fn escaped_char(&self, c: char) -> Result<char, PatternError> {
+        match c {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            c if !c.is_alphanumeric() => Ok(c),
+            c => Err(error(&format!("unknown escape `\\{c}`"))),
+        }
+    }
+
+    /// The body of a `[...]` class; the `[` is already consumed.
+    fn class(&mut self) -> Result<Node, PatternError> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut ranges = vec![];
+        let mut first = true;
+        loop {
+            let c = match self.next() {
+                Some(']') if !first => break,
+                Some('\\') => match self.next() {
+                    Some('d') => {
+                        ranges.extend_from_slice(DIGIT);
+                        first = false;
+                        continue;
+                    }
+                    Some('w') => {
+                        ranges.extend_from_slice(WORD);
+                        first = false;
+                        continue;
+                    }
+                    Some('s') => {
+                        ranges.extend_from_slice(SPACE);
+                        first = false;
+                        continue;
+                    }
+                    Some('D' | 'W' | 'S') => return Err(error("negated classes are not supported inside `[]`")),
+                    Some(c) => self.escaped_char(c)?,
+                    None => return Err(error("trailing backslash")),
+                },
+                Some(c) => c,
+                None => return Err(error("unclosed character class")),
+            };
+            first = false;
+            let is_range = self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']');
+            if is_range {
+                self.pos += 1;
+                let hi = match self.next() {
+                    Some('\\') => {
+                        let c = self.next().ok_or_else(|| error("trailing backslash"))?;
+                        self.escaped_char(c)?
+                    }
+                    Some(hi) => hi,
+                    None => return Err(error("unclosed character class")),
+                };
+                if hi < c {
+                    return Err(error("invalid class range"));
+                }
+                ranges.push((c, hi));
+            } else {
+                ranges.push((c, c));
+            }
+        }
+        Ok(Node::Class(Class { negated, ranges }))
+    }
+
+    fn quantified(&mut self, mut node: Node) -> Result<Node, PatternError> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => (0, None),
+                _ => return Ok(node),
+            };
+            let (min, max) = if self.next() == Some('{') { self.repetition()? } else { (min, max) };
+            node = Node::Repeat { node: Box::new(node), min, max };
+        }
+    }
+
+    /// `n}`, `n,}` or `n,m}`; the `{` is already consumed.
+    fn repetition(&mut self) -> Result<(usize, Option<usize>), PatternError> {
+        let min = self.number().ok_or_else(|| error("invalid repetition"))?;
+        let max = if self.peek() == Some(',') {
+            self.pos += 1;
+            self.number()
+        } else {
+            Some(min)
+        };
+        if self.next() != Some('}') || max.is_some_and(|max| max < min) {
+            return Err(error("invalid repetition"));
+        }
+        Ok((min, max))
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect::<String>().parse().ok()
+    }
+}
+
+struct Compiler {
+    program: Vec<Inst>,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> Result<usize, PatternError> {
+        if self.program.len() >= MAX_PROGRAM_LEN {
+            return Err(error("pattern too large"));
+        }
+        self.program.push(inst);
+        Ok(self.program.len() - 1)
+    }
+
+    fn alternatives(&mut self, alternatives: &[Vec<Node>]) -> Result<(), PatternError> {
+        let (last, rest) = alternatives.split_last().expect("at least one alternative");
+        let mut jumps = vec![];
+        for alt in rest {
+            let split = self.emit(Inst::Split(0, 0))?;
+            self.sequence(alt)?;
+            jumps.push(self.emit(Inst::Jmp(0))?);
+            self.program[split] = Inst::Split(split + 1, self.program.len());
+        }
+        self.sequence(last)?;
+        let end = self.program.len();
+        for jump in jumps {
+            self.program[jump] = Inst::Jmp(end);
+        }
+        Ok(())
+    }
+
+    fn sequence(&mut self, nodes: &[Node]) -> Result<(), PatternError> {
+        nodes.iter().try_for_each(|node| self.node(node))
+    }
+
+    fn node(&mut self, node: &Node) -> Result<(), PatternError> {
+        match node {
+            Node::Literal(c) => self.emit(Inst::Literal(*c)).map(drop),
+            Node::Any => self.emit(Inst::Any).map(drop),
+            Node::Class(class) => self.emit(Inst::Class(class.clone())).map(drop),
+            Node::Start => self.emit(Inst::Start).map(drop),
+            Node::End => self.emit(Inst::End).map(drop),
+            Node::Group(alternatives) => self.alternatives(alternatives),
+            Node::Repeat { node, min, max } => self.repeat(node, *min, *max),
+        }
+    }
+
+    /// `min` copies of `node`, then either a loop or `max - min` optional
+    /// copies. Empty iterations need no special casing: a state is only
+    /// added once per input position.
+    fn repeat(&mut self, node: &Node, min: usize, max: Option<usize>) -> Result<(), PatternError> {
+        for _ in 0..min {
+            self.node(node)?;
+        }
+        let Some(max) = max else {
+            let split = self.emit(Inst::Split(0, 0))?;
+            self.node(node)?;
+            self.emit(Inst::Jmp(split))?;
+            self.program[split] = Inst::Split(split + 1, self.program.len());
+            return Ok(());
+        };
+        let mut splits = vec![];
+        for _ in min..max {
+            splits.push(self.emit(Inst::Split(0, 0))?);
+            self.node(node)?;
+        }
+        let end = self.program.len();
+        for split in splits {
+            self.program[split] = Inst::Split(split + 1, end);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn is_match(pattern: &str, input: &str) -> bool {
+        Pattern::new(pattern).unwrap().is_match(input)
+    }
+
+    fn parse_err(pattern: &str) -> String {
+        Pattern::new(pattern).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_slug() {
+        assert!(is_match("^[a-z0-9-]+$", "hello-world-42"));
+        assert!(!is_match("^[a-z0-9-]+$", "Hello World"));
+        assert!(!is_match("^[a-z0-9-]+$", ""));
+    }
+
+    #[test]
+    fn test_unanchored_matches_anywhere() {
+        assert!(is_match("b+", "abbbc"));
+        assert!(!is_match("^b+", "abbbc"));
+        assert!(is_match("", "anything"));
+    }
+
+    #[test]
+    fn test_phone_number() {
+        let phone = r"^(\+\d{1,3} )?\d{3}-\d{3}-\d{4}$";
+        assert!(is_match(phone, "555-123-4567"));
+        assert!(is_match(phone, "+44 555-123-4567"));
+        assert!(!is_match(phone, "+44555-123-4567"));
+        assert!(!is_match(phone, "555-1234-567"));
+    }
+
+    #[test]
+    fn test_alternation_and_groups() {
+        assert!(is_match("^(cat|dog)s?$", "dogs"));
+        assert!(is_match("^(?:cat|dog)s?$", "cat"));
+        assert!(!is_match("^(cat|dog)s?$", "cow"));
+        assert!(is_match("^a|b$", "axx"));
+    }
+
+    #[test]
+    fn test_classes_and_escapes() {
+        assert!(is_match(r"^[^@\s]+@[^@\s]+$", "a@b"));
+        assert!(!is_match(r"^[^@\s]+@[^@\s]+$", "a b@c"));
+        assert!(is_match(r"^\w+\.\w+$", "file_1.rs"));
+        assert!(is_match(r"^\S\s\D$", "a x"));
+        assert!(is_match("^[-a]+$", "-a-"));
+        assert!(is_match("^[]a]+$", "]a"));
+    }
+
+    #[test]
+    fn test_repetition_bounds() {
+        assert!(is_match("^a{2,3}$", "aaa"));
+        assert!(!is_match("^a{2,3}$", "aaaa"));
+        assert!(is_match("^a{2,}$", "aaaaa"));
+        assert!(!is_match("^a{2}$", "a"));
+        assert!(is_match("^(a?){3}b$", "ab"));
+        assert!(is_match("^(a*)*b$", "aaab"));
+    }
+
+    #[test]
+    fn test_non_ascii() {
+        assert!(is_match("^é.$", "éa"));
+        assert!(is_match("^[à-ÿ]+$", "éè"));
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        assert_eq!(parse_err("(ab"), "unclosed group");
+        assert_eq!(parse_err("ab)"), "unmatched `)`");
+        assert_eq!(parse_err("[ab"), "unclosed character class");
+        assert_eq!(parse_err("*a"), "nothing to repeat");
+        assert_eq!(parse_err("a{3,1}"), "invalid repetition");
+        assert_eq!(parse_err("a{x}"), "invalid repetition");
+        assert_eq!(parse_err("[z-a]"), "invalid class range");
+        assert_eq!(parse_err("a\\"), "trailing backslash");
+        assert_eq!(parse_err(r"\q"), "unknown escape `\\q`");
+        assert_eq!(parse_err("(a{1000}){1000}"), "pattern too large");
+    }
+
+    #[test]
+    fn test_long_input() {
+        let slug = "a-".repeat(50_000) + "z";
+        assert!(is_match("^[a-z0-9-]+$", &slug));
+        assert!(!is_match("^[a-z0-9-]+$", &(slug + " ")));
+        assert!(is_match("^(ab|a-)*z$", &("a-".repeat(50_000) + "z")));
+    }
+
+    #[test]
+    fn test_pathological_pattern() {
+        let input = "a".repeat(10_000);
+        assert!(!is_match("^(a|a)*b$", &input));
+        assert!(is_match("^(a|a)*b$", &(input.clone() + "b")));
+        assert!(!is_match("^(a*)*b$", &input));
+        assert!(!is_match("(a+)+b", &input));
+    }
+}